@@ -0,0 +1,241 @@
+use std::fmt;
+
+pub const MIN_FILTER_SPEED: f64 = 0.5;
+pub const MAX_FILTER_SPEED: f64 = 2.0;
+pub const DEFAULT_FILTER_SPEED: f64 = 1.25;
+pub const MAX_PITCH_SEMITONES: i32 = 12;
+pub const MAX_EQ_BANDS: usize = 10;
+pub const MAX_EQ_GAIN: f64 = 20.0;
+pub const LOUDNORM_FILTER: &str = "loudnorm=I=-16:TP=-1.5:LRA=11";
+
+const FILTER_USAGE: &str = "Expected format: `!filter bassboost|nightcore|vaporwave|8d|off`, \
+`!filter speed [0.5-2]`, `!filter pitch <-12..12>` or `!filter eq <freq>:<gain> ...` \
+(e.g. `!filter eq 60:+6 3k:-2`)";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBand {
+    pub frequency: u32,
+    pub gain: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioFilter {
+    BassBoost,
+    Nightcore,
+    Vaporwave,
+    EightD,
+    Speed(f64),
+    Pitch(i32),
+    Equalizer(Vec<EqBand>),
+}
+
+impl AudioFilter {
+    pub fn ffmpeg_chain(&self) -> String {
+        match self {
+            AudioFilter::BassBoost => "bass=g=10:f=110:w=0.6".to_owned(),
+            AudioFilter::Nightcore => resample_chain(1.25),
+            AudioFilter::Vaporwave => resample_chain(0.8),
+            AudioFilter::EightD => "apulsator=hz=0.125".to_owned(),
+            AudioFilter::Speed(speed) => format!("atempo={}", speed),
+            AudioFilter::Pitch(semitones) => {
+                let factor = pitch_factor(*semitones);
+                format!("{},atempo={:.6}", resample_chain(factor), 1.0 / factor)
+            }
+            AudioFilter::Equalizer(bands) => bands
+                .iter()
+                .map(|band| format!("equalizer=f={}:t=o:w=1:g={}", band.frequency, band.gain))
+                .collect::<Vec<_>>()
+                .join(","),
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        match self {
+            AudioFilter::Nightcore => 1.25,
+            AudioFilter::Vaporwave => 0.8,
+            AudioFilter::Speed(speed) => *speed,
+            _ => 1.0,
+        }
+    }
+}
+
+impl fmt::Display for AudioFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioFilter::BassBoost => write!(f, "bassboost"),
+            AudioFilter::Nightcore => write!(f, "nightcore"),
+            AudioFilter::Vaporwave => write!(f, "vaporwave"),
+            AudioFilter::EightD => write!(f, "8D"),
+            AudioFilter::Speed(speed) => write!(f, "speed x{}", speed),
+            AudioFilter::Pitch(semitones) => write!(f, "pitch {:+} semitones", semitones),
+            AudioFilter::Equalizer(bands) => {
+                write!(f, "eq")?;
+                for band in bands {
+                    write!(f, " {}Hz {:+}dB", band.frequency, band.gain)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The full `-af` chain for a guild: its filter, then loudness normalization so
+/// boosted presets are levelled too.
+pub fn audio_filter_chain(filter: Option<&AudioFilter>, normalize: bool) -> Option<String> {
+    let chain: Vec<String> = filter
+        .map(AudioFilter::ffmpeg_chain)
+        .into_iter()
+        .chain(normalize.then(|| LOUDNORM_FILTER.to_owned()))
+        .collect();
+    (!chain.is_empty()).then(|| chain.join(","))
+}
+
+fn resample_chain(factor: f64) -> String {
+    format!(
+        "aresample=48000,asetrate={:.0},aresample=48000",
+        48_000.0 * factor
+    )
+}
+
+fn pitch_factor(semitones: i32) -> f64 {
+    2f64.powf(f64::from(semitones) / 12.0)
+}
+
+/// Parses the arguments of `!filter`; `Ok(None)` turns filtering off.
+pub fn parse_audio_filter(args: &str) -> Result<Option<AudioFilter>, &'static str> {
+    let mut parts = args.split_whitespace();
+    let Some(name) = parts.next() else {
+        return Err(FILTER_USAGE);
+    };
+    let rest: Vec<&str> = parts.collect();
+
+    let filter = match (name.to_lowercase().as_str(), rest.as_slice()) {
+        ("off" | "none" | "reset", []) => return Ok(None),
+        ("bassboost" | "bass", []) => AudioFilter::BassBoost,
+        ("nightcore", []) => AudioFilter::Nightcore,
+        ("vaporwave", []) => AudioFilter::Vaporwave,
+        ("8d", []) => AudioFilter::EightD,
+        ("speed", []) => AudioFilter::Speed(DEFAULT_FILTER_SPEED),
+        ("speed", [speed]) => speed
+            .trim_start_matches('x')
+            .parse::<f64>()
+            .ok()
+            .filter(|speed| (MIN_FILTER_SPEED..=MAX_FILTER_SPEED).contains(speed))
+            .map(AudioFilter::Speed)
+            .ok_or("Speed must be between 0.5 and 2")?,
+        ("pitch", [semitones]) => semitones
+            .parse::<i32>()
+            .ok()
+            .filter(|semitones| *semitones != 0 && semitones.abs() <= MAX_PITCH_SEMITONES)
+            .map(AudioFilter::Pitch)
+            .ok_or("Pitch must be a whole number of semitones between -12 and 12")?,
+        ("eq" | "equalizer", bands) if !bands.is_empty() && bands.len() <= MAX_EQ_BANDS => {
+            let bands = bands
+                .iter()
+                .map(|band| parse_eq_band(band))
+                .collect::<Option<Vec<_>>>()
+                .ok_or("Equalizer bands look like `60:+6` or `3k:-2` (up to ±20 dB)")?;
+            AudioFilter::Equalizer(bands)
+        }
+        _ => return Err(FILTER_USAGE),
+    };
+
+    Ok(Some(filter))
+}
+
+fn parse_eq_band(band: &str) -> Option<EqBand> {
+    let band = band.to_lowercase();
+    let (frequency, gain) = band.split_once(':')?;
+    let frequency = frequency.trim_end_matches("hz");
+    let frequency = match frequency.strip_suffix('k') {
+        Some(khz) => khz.parse::<f64>().ok()? * 1000.0,
+        None => frequency.parse::<f64>().ok()?,
+    };
+    let gain = gain.trim_end_matches("db").parse::<f64>().ok()?;
+
+    ((20.0..=20_000.0).contains(&frequency) && gain.abs() <= MAX_EQ_GAIN).then_some(EqBand {
+        frequency: frequency.round() as u32,
+        gain,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{audio_filter_chain, parse_audio_filter, AudioFilter, EqBand, LOUDNORM_FILTER};
+
+    #[test]
+    fn parses_presets_and_off() {
+        assert_eq!(parse_audio_filter("off"), Ok(None));
+        assert_eq!(
+            parse_audio_filter("Nightcore"),
+            Ok(Some(AudioFilter::Nightcore))
+        );
+        assert_eq!(parse_audio_filter("8d"), Ok(Some(AudioFilter::EightD)));
+        assert_eq!(
+            parse_audio_filter("speed"),
+            Ok(Some(AudioFilter::Speed(1.25)))
+        );
+        assert_eq!(
+            parse_audio_filter("speed x1.5"),
+            Ok(Some(AudioFilter::Speed(1.5)))
+        );
+        assert!(parse_audio_filter("speed 3").is_err());
+        assert!(parse_audio_filter("pitch 13").is_err());
+        assert!(parse_audio_filter("").is_err());
+        assert!(parse_audio_filter("bassboost please").is_err());
+    }
+
+    #[test]
+    fn parses_equalizer_bands() {
+        assert_eq!(
+            parse_audio_filter("eq 60:+6 3k:-2.5"),
+            Ok(Some(AudioFilter::Equalizer(vec![
+                EqBand {
+                    frequency: 60,
+                    gain: 6.0
+                },
+                EqBand {
+                    frequency: 3000,
+                    gain: -2.5
+                },
+            ])))
+        );
+        assert!(parse_audio_filter("eq 60:+30").is_err());
+        assert!(parse_audio_filter("eq 60").is_err());
+        assert!(parse_audio_filter("eq").is_err());
+    }
+
+    #[test]
+    fn builds_ffmpeg_chains_and_speeds() {
+        assert_eq!(
+            AudioFilter::Nightcore.ffmpeg_chain(),
+            "aresample=48000,asetrate=60000,aresample=48000"
+        );
+        assert_eq!(AudioFilter::Nightcore.speed(), 1.25);
+        assert_eq!(AudioFilter::Pitch(12).speed(), 1.0);
+        assert_eq!(
+            AudioFilter::Pitch(12).ffmpeg_chain(),
+            "aresample=48000,asetrate=96000,aresample=48000,atempo=0.500000"
+        );
+        assert_eq!(
+            parse_audio_filter("eq 1k:-3")
+                .unwrap()
+                .unwrap()
+                .ffmpeg_chain(),
+            "equalizer=f=1000:t=o:w=1:g=-3"
+        );
+    }
+
+    #[test]
+    fn normalizes_after_the_chosen_filter() {
+        assert_eq!(audio_filter_chain(None, false), None);
+        assert_eq!(
+            audio_filter_chain(None, true).as_deref(),
+            Some(LOUDNORM_FILTER)
+        );
+        assert_eq!(
+            audio_filter_chain(Some(&AudioFilter::BassBoost), true),
+            Some(format!("bass=g=10:f=110:w=0.6,{}", LOUDNORM_FILTER))
+        );
+    }
+}
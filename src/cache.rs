@@ -0,0 +1,298 @@
+use crate::resources::Node;
+use crate::utils::{is_valid_youtube_url, valid_youtube_id};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+pub const DEFAULT_CACHE_MAX_MB: u64 = 2048;
+const CACHE_EXTENSION: &str = "flac";
+const PARTIAL_EXTENSION: &str = "part";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    downloading: HashSet<String>,
+}
+
+/// FLAC copies of YouTube songs under `AUDIO_CACHE_DIR`, keyed by video id and
+/// evicted least recently played first once they outgrow `AUDIO_CACHE_MAX_MB`.
+#[derive(Default)]
+pub struct AudioCache {
+    dir: Option<PathBuf>,
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+}
+
+impl AudioCache {
+    pub fn from_env() -> Self {
+        let Some(dir) = env::var_os("AUDIO_CACHE_DIR").filter(|dir| !dir.is_empty()) else {
+            return Self::default();
+        };
+        let max_mb = env::var("AUDIO_CACHE_MAX_MB")
+            .ok()
+            .and_then(|mb| mb.trim().parse().ok())
+            .unwrap_or(DEFAULT_CACHE_MAX_MB);
+
+        let dir = PathBuf::from(dir);
+        match Self::open(dir.clone(), max_mb.saturating_mul(1024 * 1024)) {
+            Ok(cache) => cache,
+            Err(why) => {
+                println!("Error opening audio cache {}: {:?}", dir.display(), why);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn open(dir: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        let mut entries = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                // Left behind by a download that never finished.
+                Some(PARTIAL_EXTENSION) => {
+                    let _ = fs::remove_file(&path);
+                }
+                Some(CACHE_EXTENSION) => {
+                    let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                        continue;
+                    };
+                    let metadata = entry.metadata()?;
+                    entries.insert(
+                        key.to_owned(),
+                        CacheEntry {
+                            size: metadata.len(),
+                            last_used: metadata.modified()?,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let cache = AudioCache {
+            dir: Some(dir),
+            max_bytes,
+            state: Mutex::new(CacheState {
+                entries,
+                downloading: HashSet::new(),
+            }),
+        };
+        cache.evict();
+
+        let state = cache.lock();
+        println!(
+            "Audio cache holds {} songs ({} MB)",
+            state.entries.len(),
+            state.entries.values().map(|entry| entry.size).sum::<u64>() / (1024 * 1024)
+        );
+        drop(state);
+        Ok(cache)
+    }
+
+    pub fn lookup(&self, node: &Node) -> Option<PathBuf> {
+        let key = cache_key(node)?;
+        let path = self.path(key)?;
+
+        let last_used = SystemTime::now();
+        self.lock().entries.get_mut(key)?.last_used = last_used;
+
+        // The modified time carries the LRU order across restarts.
+        let touched = path.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(why) = File::options()
+                .write(true)
+                .open(&touched)
+                .and_then(|file| file.set_modified(last_used))
+            {
+                println!(
+                    "Error touching cached audio {}: {:?}",
+                    touched.display(),
+                    why
+                );
+            }
+        });
+        Some(path)
+    }
+
+    pub fn remove(&self, node: &Node) {
+        let Some(key) = cache_key(node) else {
+            return;
+        };
+        if self.lock().entries.remove(key).is_some() {
+            if let Some(path) = self.path(key) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Claims `node`'s slot for a new copy, returning the file to write it to,
+    /// unless it's already cached or being stored.
+    pub fn start_store(&self, node: &Node) -> Option<PathBuf> {
+        let key = cache_key(node)?;
+        let path = self.path(key)?;
+        let mut state = self.lock();
+        if state.entries.contains_key(key) || !state.downloading.insert(key.to_owned()) {
+            return None;
+        }
+        Some(path.with_extension(PARTIAL_EXTENSION))
+    }
+
+    /// Keeps the copy `start_store` handed out if it was `stored` completely,
+    /// and frees the slot either way. Blocks on the filesystem.
+    pub fn finish_store(&self, node: &Node, stored: io::Result<()>) {
+        let Some(key) = cache_key(node) else {
+            return;
+        };
+        let Some(path) = self.path(key) else {
+            return;
+        };
+        let partial = path.with_extension(PARTIAL_EXTENSION);
+        let stored = stored
+            .and_then(|_| fs::rename(&partial, &path))
+            .and_then(|_| fs::metadata(&path));
+
+        let mut state = self.lock();
+        state.downloading.remove(key);
+        match stored {
+            Ok(metadata) => {
+                state.entries.insert(
+                    key.to_owned(),
+                    CacheEntry {
+                        size: metadata.len(),
+                        last_used: SystemTime::now(),
+                    },
+                );
+                drop(state);
+                self.evict();
+            }
+            Err(why) => {
+                drop(state);
+                println!("Error caching {}: {:?}", node.url, why);
+                let _ = fs::remove_file(&partial);
+            }
+        }
+    }
+
+    fn evict(&self) {
+        let Some(dir) = self.dir.as_deref() else {
+            return;
+        };
+
+        let mut state = self.lock();
+        for key in lru_evictions(&state.entries, self.max_bytes) {
+            state.entries.remove(&key);
+            if let Err(why) = fs::remove_file(entry_path(dir, &key)) {
+                println!("Error evicting cached audio {}: {:?}", key, why);
+            }
+        }
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        Some(entry_path(self.dir.as_deref()?, key))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Only finished YouTube videos are cached, so the video id is a safe file name.
+fn cache_key(node: &Node) -> Option<&str> {
+    let id = node.metadata.id.as_str();
+    (!node.is_live() && is_valid_youtube_url(&node.url) && valid_youtube_id(id)).then_some(id)
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(key).with_extension(CACHE_EXTENSION)
+}
+
+fn lru_evictions(entries: &HashMap<String, CacheEntry>, max_bytes: u64) -> Vec<String> {
+    let mut total: u64 = entries.values().map(|entry| entry.size).sum();
+    let mut by_age: Vec<_> = entries.iter().collect();
+    by_age.sort_by_key(|(_, entry)| entry.last_used);
+
+    let mut evicted = Vec::new();
+    for (key, entry) in by_age {
+        if total <= max_bytes {
+            break;
+        }
+        total -= entry.size;
+        evicted.push(key.clone());
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_key, lru_evictions, CacheEntry};
+    use crate::resources::Node;
+    use crate::utils::VideoMetadata;
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
+
+    #[test]
+    fn evicts_least_recently_used_until_under_the_limit() {
+        let entry = |size, age: u64| CacheEntry {
+            size,
+            last_used: SystemTime::UNIX_EPOCH + Duration::from_secs(1000 - age),
+        };
+        let entries = HashMap::from([
+            ("newest".to_owned(), entry(40, 0)),
+            ("oldest".to_owned(), entry(30, 300)),
+            ("middle".to_owned(), entry(50, 200)),
+        ]);
+
+        assert_eq!(lru_evictions(&entries, 120), Vec::<String>::new());
+        assert_eq!(lru_evictions(&entries, 100), ["oldest"]);
+        assert_eq!(lru_evictions(&entries, 40), ["oldest", "middle"]);
+        assert_eq!(lru_evictions(&entries, 0).len(), 3);
+    }
+
+    #[test]
+    fn caches_only_youtube_videos_by_id() {
+        let node = |url: &str, id: &str| {
+            Node::from_metadata(
+                url.to_owned(),
+                VideoMetadata {
+                    id: id.to_owned(),
+                    duration: Some(Duration::from_secs(200)),
+                    ..VideoMetadata::default()
+                },
+            )
+        };
+
+        let video = node("https://www.youtube.com/watch?v=dQw4w9WgXcQ", "dQw4w9WgXcQ");
+        assert_eq!(cache_key(&video), Some("dQw4w9WgXcQ"));
+        assert_eq!(
+            cache_key(&node("https://soundcloud.com/a/b", "dQw4w9WgXcQ")),
+            None
+        );
+        assert_eq!(
+            cache_key(&node("https://youtu.be/dQw4w9WgXcQ", "../../etc")),
+            None
+        );
+
+        let mut live = video.clone();
+        live.metadata.is_live = true;
+        assert_eq!(cache_key(&live), None);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::utils::{
+    audio_file_extension, file_input, probe_audio_file, SourceResolver, VideoMetadata,
+};
+use serenity::async_trait;
+use songbird::input::Input;
+use std::{
+    env, fs, io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+pub const LOCAL_URL_PREFIX: &str = "local:";
+pub const LIBRARY_PAGE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LibraryTrack {
+    pub path: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub duration: Duration,
+}
+
+impl LibraryTrack {
+    pub fn url(&self) -> String {
+        format!("{}{}", LOCAL_URL_PREFIX, self.path)
+    }
+
+    pub fn display_name(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    pub fn metadata(&self) -> VideoMetadata {
+        VideoMetadata {
+            title: self.title.clone(),
+            uploader: self.artist.clone(),
+            duration: Some(self.duration),
+            webpage_url: self.url(),
+            ..VideoMetadata::default()
+        }
+    }
+
+    fn matches(&self, words: &[String]) -> bool {
+        let haystack = format!(
+            "{} {} {}",
+            self.artist.as_deref().unwrap_or_default(),
+            self.title,
+            self.path
+        )
+        .to_lowercase();
+        words.iter().all(|word| haystack.contains(word.as_str()))
+    }
+}
+
+#[derive(Default)]
+pub struct MusicLibrary {
+    root: Option<PathBuf>,
+    tracks: Vec<LibraryTrack>,
+}
+
+impl MusicLibrary {
+    pub fn from_env() -> Self {
+        match music_dir() {
+            Some(root) => Self::scan(root),
+            None => Self::default(),
+        }
+    }
+
+    pub fn scan(root: PathBuf) -> Self {
+        let mut files = Vec::new();
+        if let Err(why) = collect_audio_files(&root, &mut files) {
+            println!(
+                "Error reading music directory {}: {:?}",
+                root.display(),
+                why
+            );
+        }
+        files.sort();
+
+        let tracks: Vec<LibraryTrack> = files
+            .iter()
+            .filter_map(|file| match index_file(&root, file) {
+                Ok(track) => Some(track),
+                Err(why) => {
+                    println!("Skipping {}: {:?}", file.display(), why);
+                    None
+                }
+            })
+            .collect();
+        println!(
+            "Indexed {} local tracks from {}",
+            tracks.len(),
+            root.display()
+        );
+
+        MusicLibrary {
+            root: Some(root),
+            tracks,
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.root.is_some()
+    }
+
+    pub fn tracks(&self) -> &[LibraryTrack] {
+        &self.tracks
+    }
+
+    pub fn search(&self, query: &str) -> Vec<&LibraryTrack> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        self.tracks
+            .iter()
+            .filter(|track| track.matches(&words))
+            .collect()
+    }
+}
+
+pub fn library_page(tracks: &[LibraryTrack], page: usize) -> Option<String> {
+    let pages = tracks.len().div_ceil(LIBRARY_PAGE_SIZE).max(1);
+    if page == 0 || page > pages {
+        return None;
+    }
+
+    let start = (page - 1) * LIBRARY_PAGE_SIZE;
+    let mut listing = format!("📀 **Local Library** (page {}/{})\n```", page, pages);
+    if tracks.is_empty() {
+        listing.push_str("No tracks found\n");
+    }
+    for (index, track) in tracks
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take(LIBRARY_PAGE_SIZE)
+    {
+        let secs = track.duration.as_secs();
+        listing.push_str(&format!(
+            "{}. {} ({}:{:02})\n",
+            index + 1,
+            track.display_name(),
+            secs / 60,
+            secs % 60
+        ));
+    }
+    listing.push_str("```");
+    Some(listing)
+}
+
+pub fn music_dir() -> Option<PathBuf> {
+    env::var_os("MUSIC_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Maps a `local:` URL back to a file, refusing anything that escapes `MUSIC_DIR`.
+pub fn local_file_path(url: &str) -> Option<PathBuf> {
+    let relative = Path::new(url.strip_prefix(LOCAL_URL_PREFIX)?);
+    let mut components = relative.components().peekable();
+    components.peek()?;
+    if !components.all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+
+    Some(music_dir()?.join(relative))
+}
+
+fn collect_audio_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_audio_files(&path, files)?;
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(audio_file_extension)
+            .is_some()
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn index_file(root: &Path, file: &Path) -> io::Result<LibraryTrack> {
+    let relative = file
+        .strip_prefix(root)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let path = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "path isn't valid UTF-8"))?
+        .join("/");
+    let probe = probe_audio_file(file)?;
+
+    Ok(LibraryTrack {
+        title: probe.title.unwrap_or_else(|| file_stem(file)),
+        artist: probe.artist,
+        duration: probe.duration,
+        path,
+    })
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `local:` URLs produced by `!local`, decoded in-process instead of through ffmpeg.
+pub struct LocalFileResolver;
+
+#[async_trait]
+impl SourceResolver for LocalFileResolver {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn handles(&self, url: &str) -> bool {
+        url.starts_with(LOCAL_URL_PREFIX)
+    }
+
+    async fn metadata(&self, url: &str) -> io::Result<VideoMetadata> {
+        let path = local_file_path(url)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not in the music library"))?;
+        let probe = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || probe_audio_file(&path)
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        Ok(VideoMetadata {
+            title: probe.title.unwrap_or_else(|| file_stem(&path)),
+            uploader: probe.artist,
+            duration: Some(probe.duration),
+            webpage_url: url.to_string(),
+            ..VideoMetadata::default()
+        })
+    }
+
+    fn create_input(&self, url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+        let path = local_file_path(url)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not in the music library"))?;
+        file_input(&path, start, filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{library_page, local_file_path, LibraryTrack, MusicLibrary};
+    use std::time::Duration;
+
+    fn track(path: &str, title: &str, artist: Option<&str>) -> LibraryTrack {
+        LibraryTrack {
+            path: path.to_string(),
+            title: title.to_string(),
+            artist: artist.map(str::to_string),
+            duration: Duration::from_secs(185),
+        }
+    }
+
+    #[test]
+    fn searches_every_word_across_tags_and_paths() {
+        let library = MusicLibrary {
+            root: None,
+            tracks: vec![
+                track("rips/side_a.flac", "Blue Monday", Some("New Order")),
+                track("rips/side_b.flac", "Temptation", Some("New Order")),
+                track("misc/monday.mp3", "monday", None),
+            ],
+        };
+
+        let titles = |query| {
+            library
+                .search(query)
+                .iter()
+                .map(|track| track.title.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles("new order monday"), ["Blue Monday"]);
+        assert_eq!(titles("MONDAY"), ["Blue Monday", "monday"]);
+        assert_eq!(titles("side_b"), ["Temptation"]);
+        assert!(titles("   ").is_empty());
+    }
+
+    #[test]
+    fn pages_the_library_listing() {
+        let tracks: Vec<_> = (1..=12)
+            .map(|n| track(&format!("{n}.mp3"), &format!("Song {n}"), None))
+            .collect();
+
+        let second = library_page(&tracks, 2).unwrap();
+        assert!(second.contains("(page 2/2)"));
+        assert!(second.contains("11. Song 11 (3:05)"));
+        assert!(!second.contains("10. Song 10"));
+        assert!(library_page(&tracks, 3).is_none());
+        assert!(library_page(&[], 1).unwrap().contains("No tracks found"));
+    }
+
+    #[test]
+    fn local_urls_stay_inside_the_library() {
+        assert_eq!(track("a/b.mp3", "b", None).url(), "local:a/b.mp3");
+        for url in ["local:../secret.mp3", "local:/etc/passwd", "local:"] {
+            assert!(local_file_path(url).is_none(), "{url}");
+        }
+    }
+}
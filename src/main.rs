@@ -1,17 +1,30 @@
 use serenity::{
     async_trait,
     client::{Client, EventHandler},
-    model::{channel::Message, gateway::GatewayIntents},
+    model::{channel::Message, gateway::GatewayIntents, voice::VoiceState},
     prelude::Context,
 };
 use songbird::SerenityInit;
 use std::env;
+pub mod audio_filter;
+use audio_filter::parse_audio_filter;
+pub mod cache;
+use cache::AudioCache;
+pub mod library;
+use library::MusicLibrary;
 pub mod resources;
 use resources::*;
+pub mod settings;
+use settings::{SettingsStore, MAX_CROSSFADE_SECONDS, MAX_VOLUME};
 pub mod utils;
 use utils::*;
 pub mod systems;
-use systems::{chat_gpt, generate_image, loop_song, manage_queue, say_queue, skip_all_enabled};
+use systems::{
+    can_skip_without_vote, chat_gpt, dj_role, generate_image, has_dj_rights, local_music,
+    loop_song, manage_queue, parse_queue_position, parse_repeat_command, queue_attachments,
+    require_dj, say_queue, skip, toggle_stay_in_voice, voice_state_changed, vote_skip_setting,
+    PlayerCommand,
+};
 
 #[tokio::main]
 async fn main() {
@@ -19,7 +32,11 @@ async fn main() {
     let token = env::var("DISCORD_KEY").expect("Expected a token in the environment");
 
     let mut client = Client::builder(&token, GatewayIntents::all())
-        .event_handler(Handler::default())
+        .event_handler(Handler::new(
+            SettingsStore::from_env(),
+            MusicLibrary::from_env(),
+            AudioCache::from_env(),
+        ))
         .register_songbird()
         .await
         .expect("Err creating client");
@@ -57,6 +74,16 @@ async fn delete_command_message(ctx: &Context, msg: &Message) {
     }
 }
 
+async fn say_player_reply(ctx: &Context, msg: &Message, reply: &str) {
+    if reply.is_empty() {
+        return;
+    }
+
+    if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+        println!("Error sending player reply: {:?}", why);
+    }
+}
+
 async fn openai_key_or_reply(ctx: &Context, msg: &Message) -> Option<String> {
     match env::var("OPENAI_KEY") {
         Ok(api_key) => Some(api_key),
@@ -89,46 +116,330 @@ impl EventHandler for Handler {
                 .await;
             return;
         };
-        let manager = songbird::get(&ctx)
-            .await
-            .expect("Songbird Voice client placed in at initialization.")
-            .clone();
 
-        if body.starts_with("https://") || body.starts_with("http://") {
-            manage_queue(body, msg.clone(), guild_id, &ctx, manager, self).await;
+        let has_audio_attachment = msg
+            .attachments
+            .iter()
+            .any(|attachment| audio_file_extension(&attachment.filename).is_some());
+
+        if has_audio_attachment && (body.is_empty() || is_exact_command(body, "play")) {
+            queue_attachments(msg.clone(), guild_id, &ctx, self).await;
+        } else if body.starts_with("https://") || body.starts_with("http://") {
+            manage_queue(body, msg.clone(), guild_id, &ctx, self, false, 0).await;
         } else if is_exact_command(body, "q") {
             delete_command_message(&ctx, &msg).await;
-            let queue = VIDEO_QUEUE.lock().await;
-            let queue_clone = queue.clone();
-            drop(queue);
-            let current_song = {
-                let current_song = self.current_song.lock().await;
-                current_song.clone()
-            };
+            let snapshot = self.player(&ctx, guild_id).await.queue().await;
 
-            say_queue(msg.clone(), &ctx, current_song, queue_clone).await;
+            say_queue(
+                msg.clone(),
+                &ctx,
+                snapshot.current_song,
+                snapshot.position,
+                snapshot.queue,
+            )
+            .await;
         } else if is_exact_command(body, "skip") {
-            skip_all_enabled(self, guild_id, manager).await;
-        } else if is_exact_command(body, "leave") {
-            if manager.get(guild_id).is_some() {
-                if let Err(why) = manager.remove(guild_id).await {
-                    println!("Error leaving voice channel: {:?}", why);
+            skip(self, msg.clone(), guild_id, &ctx).await;
+        } else if let Some(dj_args) = command_arg(body, "djrole") {
+            delete_command_message(&ctx, &msg).await;
+            dj_role(self, dj_args, msg.clone(), guild_id, &ctx).await;
+        } else if let Some(vote_skip_args) = command_arg(body, "voteskip") {
+            delete_command_message(&ctx, &msg).await;
+            vote_skip_setting(self, vote_skip_args, msg.clone(), guild_id, &ctx).await;
+        } else if let Some(position) = command_arg(body, "skipto") {
+            delete_command_message(&ctx, &msg).await;
+            let Some(position) = parse_queue_position(position) else {
+                say_player_reply(&ctx, &msg, "Expected format: `!skipto <n>`").await;
+                return;
+            };
+            let is_dj = can_skip_without_vote(&ctx, &msg, &self.settings.get(guild_id).await);
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::SkipTo {
+                    position,
+                    is_dj,
+                    reply,
+                })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(position) = command_arg(body, "remove") {
+            delete_command_message(&ctx, &msg).await;
+            let Some(position) = parse_queue_position(position) else {
+                say_player_reply(&ctx, &msg, "Expected format: `!remove <n>`").await;
+                return;
+            };
+            let user_id = msg.author.id;
+            let is_dj = has_dj_rights(&ctx, &msg, &self.settings.get(guild_id).await);
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::Remove {
+                    position,
+                    user_id,
+                    is_dj,
+                    reply,
+                })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(positions) = command_arg(body, "move") {
+            delete_command_message(&ctx, &msg).await;
+            let mut positions = positions.split_whitespace().map(parse_queue_position);
+            let (Some(Some(from)), Some(Some(to)), None) =
+                (positions.next(), positions.next(), positions.next())
+            else {
+                say_player_reply(&ctx, &msg, "Expected format: `!move <from> <to>`").await;
+                return;
+            };
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::Move { from, to, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if is_exact_command(body, "clear") {
+            delete_command_message(&ctx, &msg).await;
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
+            }
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(PlayerCommand::Clear)
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if is_exact_command(body, "shuffle") {
+            delete_command_message(&ctx, &msg).await;
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(PlayerCommand::Shuffle)
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if is_exact_command(body, "undo") {
+            delete_command_message(&ctx, &msg).await;
+            let user_id = msg.author.id;
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::Undo { user_id, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(query) = command_arg(body, "playnext") {
+            let query = query.trim();
+            if query.is_empty() {
+                let _ = msg
+                    .reply(&ctx, "Send a URL or search query after `!playnext`.")
+                    .await;
+                return;
+            }
+
+            if query.starts_with("https://") || query.starts_with("http://") {
+                manage_queue(query, msg.clone(), guild_id, &ctx, self, true, 0).await;
+                return;
+            }
+
+            match get_searched_url(query).await {
+                Ok(url) => {
+                    manage_queue(url.as_str(), msg.clone(), guild_id, &ctx, self, true, 0).await;
+                }
+                Err(why) => {
+                    println!("Error searching YouTube: {:?}", why);
+                    let _ = msg
+                        .reply(&ctx, format!("Couldn't search YouTube: {}", why))
+                        .await;
                 }
-                skip_all_enabled(self, guild_id, manager).await;
             }
-            {
-                let mut queue = VIDEO_QUEUE.lock().await;
-                queue.clear();
+        } else if is_exact_command(body, "pause") {
+            delete_command_message(&ctx, &msg).await;
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(PlayerCommand::Pause)
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if is_exact_command(body, "resume") {
+            delete_command_message(&ctx, &msg).await;
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(PlayerCommand::Resume)
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(timestamp) = command_arg(body, "seek") {
+            delete_command_message(&ctx, &msg).await;
+            let Some(target) = parse_seek_target(timestamp) else {
+                let _ = msg
+                    .channel_id
+                    .say(
+                        &ctx.http,
+                        "Expected format: `!seek 1:30`, `!seek +30` or `!seek -15`",
+                    )
+                    .await;
+                return;
+            };
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::Seek { target, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(volume) = command_arg(body, "volume") {
+            delete_command_message(&ctx, &msg).await;
+            let volume = volume.trim();
+            if volume.is_empty() {
+                let settings = self.settings.get(guild_id).await;
+                let _ = msg
+                    .channel_id
+                    .say(&ctx.http, format!("Volume is {}%", settings.volume))
+                    .await;
+                return;
             }
-            {
-                let mut current_song = self.current_song.lock().await;
-                *current_song = None;
+
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
+            }
+            let Some(volume) = volume
+                .trim_end_matches('%')
+                .parse::<u16>()
+                .ok()
+                .filter(|volume| *volume <= MAX_VOLUME)
+            else {
+                let _ = msg
+                    .channel_id
+                    .say(
+                        &ctx.http,
+                        format!("Expected format: `!volume <0-{}>`", MAX_VOLUME),
+                    )
+                    .await;
+                return;
+            };
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::SetVolume { volume, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(filter_args) = command_arg(body, "filter") {
+            delete_command_message(&ctx, &msg).await;
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
             }
+            let filter = match parse_audio_filter(filter_args) {
+                Ok(filter) => filter,
+                Err(message) => {
+                    say_player_reply(&ctx, &msg, message).await;
+                    return;
+                }
+            };
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::SetFilter { filter, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(normalize) = command_arg(body, "normalize") {
+            delete_command_message(&ctx, &msg).await;
+            let normalize = match normalize.trim() {
+                "" => {
+                    let settings = self.settings.get(guild_id).await;
+                    let state = if settings.normalize { "on" } else { "off" };
+                    say_player_reply(&ctx, &msg, &format!("Loudness normalization is {}", state))
+                        .await;
+                    return;
+                }
+                "on" => true,
+                "off" => false,
+                _ => {
+                    say_player_reply(&ctx, &msg, "Expected format: `!normalize on|off`").await;
+                    return;
+                }
+            };
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
+            }
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::SetNormalize { normalize, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(seconds) = command_arg(body, "crossfade") {
+            delete_command_message(&ctx, &msg).await;
+            let seconds = match seconds.trim().trim_end_matches('s') {
+                "" => {
+                    let settings = self.settings.get(guild_id).await;
+                    let reply = match settings.crossfade().as_secs() {
+                        0 => "Crossfade is off".to_owned(),
+                        seconds => format!("Crossfade is {} seconds", seconds),
+                    };
+                    say_player_reply(&ctx, &msg, &reply).await;
+                    return;
+                }
+                "off" => 0,
+                seconds => match seconds
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|seconds| *seconds <= MAX_CROSSFADE_SECONDS)
+                {
+                    Some(seconds) => seconds,
+                    None => {
+                        say_player_reply(
+                            &ctx,
+                            &msg,
+                            &format!(
+                                "Expected format: `!crossfade <0-{}>` or `!crossfade off`",
+                                MAX_CROSSFADE_SECONDS
+                            ),
+                        )
+                        .await;
+                        return;
+                    }
+                },
+            };
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
+            }
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::SetCrossfade { seconds, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if is_exact_command(body, "247") {
+            delete_command_message(&ctx, &msg).await;
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
+            }
+            let reply = toggle_stay_in_voice(self, guild_id).await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if is_exact_command(body, "leave") {
+            if !require_dj(self, &ctx, &msg, guild_id).await {
+                return;
+            }
+            self.player(&ctx, guild_id).await.send(PlayerCommand::Stop);
         } else if is_exact_command(body, "help") {
             delete_command_message(&ctx, &msg).await;
             if let Err(why) = msg.channel_id.say(&ctx.http, HELP_MESSAGE).await {
                 println!("Error sending help message: {:?}", why);
             }
+        } else if let Some(repeat_args) = command_arg(body, "repeat") {
+            delete_command_message(&ctx, &msg).await;
+            let repeat = match parse_repeat_command(repeat_args) {
+                Ok(repeat) => repeat,
+                Err(message) => {
+                    say_player_reply(&ctx, &msg, message).await;
+                    return;
+                }
+            };
+            let reply = self
+                .player(&ctx, guild_id)
+                .await
+                .request(|reply| PlayerCommand::Repeat { repeat, reply })
+                .await;
+            say_player_reply(&ctx, &msg, &reply).await;
+        } else if let Some(local_args) = command_arg(body, "local") {
+            local_music(self, local_args, msg.clone(), guild_id, &ctx).await;
         } else if let Some(loop_args) = command_arg(body, "loop") {
             delete_command_message(&ctx, &msg).await;
             if loop_args.trim().is_empty() {
@@ -138,13 +449,7 @@ impl EventHandler for Handler {
                     .await;
                 return;
             }
-            if let Err(why) = loop_song(self, loop_args, msg.clone(), &ctx).await {
-                println!("Error looping song: {:?}", why);
-                let _ = msg
-                    .channel_id
-                    .say(&ctx.http, format!("Couldn't loop that song: {}", why))
-                    .await;
-            }
+            loop_song(self, loop_args, msg.clone(), guild_id, &ctx).await;
         } else if let Some(query) = command_arg(body, "play") {
             let query = query.trim();
             if query.is_empty() {
@@ -154,7 +459,7 @@ impl EventHandler for Handler {
 
             match get_searched_url(query).await {
                 Ok(url) => {
-                    manage_queue(url.as_str(), msg.clone(), guild_id, &ctx, manager, self).await
+                    manage_queue(url.as_str(), msg.clone(), guild_id, &ctx, self, false, 0).await;
                 }
                 Err(why) => {
                     println!("Error searching YouTube: {:?}", why);
@@ -216,6 +521,10 @@ impl EventHandler for Handler {
             }
         }
     }
+
+    async fn voice_state_update(&self, ctx: Context, old: Option<VoiceState>, new: VoiceState) {
+        voice_state_changed(self, &ctx, old, new).await;
+    }
 }
 
 #[cfg(test)]
@@ -1,31 +1,68 @@
-use lazy_static::lazy_static;
+use crate::cache::AudioCache;
+use crate::library::MusicLibrary;
+use crate::settings::SettingsStore;
+use crate::systems::PlayerHandle;
+use crate::utils::VideoMetadata;
+use serenity::{
+    model::prelude::{GuildId, UserId},
+    prelude::Context,
+};
 use std::{
-    collections::VecDeque,
-    sync::{atomic::AtomicBool, Arc},
-    time::Duration,
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::sync::Mutex;
 
-lazy_static! {
-    #[derive(Debug, Clone, Copy)]
-    pub static ref VIDEO_QUEUE: Mutex<VecDeque<Node>> = Mutex::new(VecDeque::new());
-}
-
 pub const NUMBER_OF_PROGRESS_BARS: u64 = 49;
+pub const LIVE_TRACKER_INTERVAL: Duration = Duration::from_secs(5);
+pub const MAX_PLAYLIST_TRACKS: usize = 100;
+/// How much of an attachment is fetched to read its tags and length; room for
+/// cover art in the tags plus some audio.
+pub const ATTACHMENT_PROBE_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_PREFETCH_SECONDS: u64 = 10;
+pub const DEFAULT_AUTO_LEAVE_EMPTY_SECONDS: u64 = 60;
+pub const DEFAULT_AUTO_LEAVE_IDLE_MINUTES: u64 = 5;
+pub const AUTO_LEAVE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 pub const HELP_MESSAGE: &str = "💅🏻 **Woman Commands** ☕\n\
 ```markdown\n\
-1. !<url>               -- Add a YouTube video to the queue\n\
-2. !play <query>        -- Plays the first YT search result\n\
-3. !loop <count> <url>  -- Loop a song \n\
-4. !q                   -- Display the current audio queue\n\
-5. !skip                -- Skip the currently playing song\n\
-6. !leave               -- Leave the voice channel and clear the queue\n\
-7. !image               -- Everything after \"!image\" is an image prompt\n\
-8. !                    -- Everything after \"!\" is a GPT prompt\n\
-9. !help                -- Displays this page\n\
+1.  !<url>                  -- Queue a YouTube/SoundCloud/Bandcamp/audio link or playlist\n\
+2.  !play <query>           -- Plays the first YT search result\n\
+3.  !play + audio file      -- Queue an attached mp3/ogg/wav/flac (or just !)\n\
+4.  !local <search>         -- Queue matching songs from the local library\n\
+5.  !local list [page]      -- Browse the local library\n\
+6.  !playnext <url|query>   -- Put a song at the front of the queue\n\
+7.  !loop <count> <url>     -- Queue a song to play <count> times\n\
+8.  !repeat off|one|all     -- Repeat nothing, the current song, or the queue\n\
+9.  !repeat one <count>     -- Play the current song <count> more times\n\
+10. !q                      -- Display the current audio queue\n\
+11. !skip                   -- Skip the current song, or vote to when vote-skip is on\n\
+12. !voteskip on|off|<n>%   -- Make !skip a vote needing n% of listeners\n\
+13. !djrole @role|off       -- Role needed to leave, clear, filter, set volume...\n\
+14. !skipto <n>             -- Skip straight to song n in the queue\n\
+15. !remove <n>             -- Remove song n from the queue\n\
+16. !move <from> <to>       -- Move a queued song to a new position\n\
+17. !clear                  -- Clear the queue but keep the current song\n\
+18. !shuffle                -- Shuffle the queued songs\n\
+19. !undo                   -- Remove the last song you queued\n\
+20. !pause                  -- Pause the currently playing song\n\
+21. !resume                 -- Resume a paused song\n\
+22. !seek <time>            -- Jump to 1:30, or move +30 / -15 seconds\n\
+23. !volume <0-200>         -- Set the playback volume for this server\n\
+24. !filter <preset>|off    -- bassboost, nightcore, vaporwave, 8d, speed [x], pitch <n>\n\
+25. !filter eq 60:+6 3k:-2  -- Custom equalizer, frequency:gain in dB\n\
+26. !normalize on|off       -- Even out loudness between songs (EBU R128)\n\
+27. !crossfade <0-12>|off   -- Fade between songs for this many seconds\n\
+28. !leave                  -- Leave the voice channel and clear the queue\n\
+29. !247                    -- Stay in voice even when alone or idle (toggle)\n\
+30. !image                  -- Everything after \"!image\" is an image prompt\n\
+31. !                       -- Everything after \"!\" is a GPT prompt\n\
+32. !help                   -- Displays this page\n\
 ```";
 
+/// Input flags for ffmpeg reading over HTTP, so a network blip resumes the
+/// stream instead of ending the song.
 pub const FFMPEG_OPTIONS: [&str; 6] = [
     "-reconnect",
     "1",
@@ -35,22 +72,16 @@ pub const FFMPEG_OPTIONS: [&str; 6] = [
     "5",
 ];
 
-pub const AUDIO_OPTIONS: [&str; 9] = [
-    "-f",
-    "s16le",
-    "-ac",
-    "2",
-    "-ar",
-    "48000",
-    "-acodec",
-    "pcm_f32le",
-    "-",
-];
-
 #[derive(Debug, Clone)]
 pub struct Node {
     pub url: String,
     pub duration: Duration,
+    /// Read once at queue time so displaying the song never asks yt-dlp again.
+    pub metadata: VideoMetadata,
+    pub start: Duration,
+    pub requested_by: Option<UserId>,
+    pub requested_at: Instant,
+    pub repeats: usize,
 }
 
 impl Node {
@@ -58,11 +89,63 @@ impl Node {
         Node {
             url: String::new(),
             duration: Duration::new(0, 0),
+            metadata: VideoMetadata::default(),
+            start: Duration::ZERO,
+            requested_by: None,
+            requested_at: Instant::now(),
+            repeats: 0,
         }
     }
 
     pub fn from(url: String, duration: Duration) -> Self {
-        Node { url, duration }
+        Node {
+            url,
+            duration,
+            ..Node::new()
+        }
+    }
+
+    pub fn from_metadata(url: String, metadata: VideoMetadata) -> Self {
+        Node {
+            url,
+            duration: metadata.duration.unwrap_or_default(),
+            metadata,
+            ..Node::new()
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.metadata.is_live
+    }
+
+    /// The song's length, unless it's live or the probe couldn't read one.
+    pub fn known_length(&self) -> Option<Duration> {
+        (!self.is_live() && !self.duration.is_zero()).then_some(self.duration)
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.duration.saturating_sub(self.start)
+    }
+
+    /// Starts at `start` when it falls inside a song of known length.
+    pub fn starting_at(mut self, start: Option<Duration>) -> Self {
+        self.start = start
+            .filter(|start| self.known_length().is_some_and(|length| *start < length))
+            .unwrap_or_default();
+        self
+    }
+
+    pub fn title(&self) -> &str {
+        if self.metadata.title.is_empty() {
+            &self.url
+        } else {
+            &self.metadata.title
+        }
+    }
+
+    pub fn requested_by(mut self, user_id: UserId) -> Self {
+        self.requested_by = Some(user_id);
+        self
     }
 }
 
@@ -73,25 +156,44 @@ impl Default for Node {
 }
 
 pub struct Handler {
-    pub playing: Arc<Mutex<bool>>,
-    pub tracking: Arc<Mutex<bool>>,
-    pub looping: Arc<Mutex<bool>>,
-    pub current_song: Arc<Mutex<Option<Node>>>,
-    pub skip_player: Arc<AtomicBool>,
-    pub skip_tracker: Arc<AtomicBool>,
-    pub skip_loop: Arc<AtomicBool>,
+    pub players: Arc<Mutex<HashMap<GuildId, PlayerHandle>>>,
+    pub settings: Arc<SettingsStore>,
+    pub library: Arc<MusicLibrary>,
+    pub cache: Arc<AudioCache>,
 }
 
-impl Default for Handler {
-    fn default() -> Self {
+impl Handler {
+    pub fn new(settings: SettingsStore, library: MusicLibrary, cache: AudioCache) -> Self {
         Handler {
-            playing: Arc::new(Mutex::new(false)),
-            tracking: Arc::new(Mutex::new(false)),
-            looping: Arc::new(Mutex::new(false)),
-            current_song: Arc::new(Mutex::new(None)),
-            skip_player: Arc::new(AtomicBool::new(false)),
-            skip_tracker: Arc::new(AtomicBool::new(false)),
-            skip_loop: Arc::new(AtomicBool::new(false)),
+            players: Arc::new(Mutex::new(HashMap::new())),
+            settings: Arc::new(settings),
+            library: Arc::new(library),
+            cache: Arc::new(cache),
         }
     }
+
+    pub async fn existing_player(&self, guild_id: GuildId) -> Option<PlayerHandle> {
+        self.players.lock().await.get(&guild_id).cloned()
+    }
+
+    pub async fn player(&self, ctx: &Context, guild_id: GuildId) -> PlayerHandle {
+        let mut players = self.players.lock().await;
+        if let Some(player) = players.get(&guild_id) {
+            return player.clone();
+        }
+
+        let manager = songbird::get(ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialization.")
+            .clone();
+        let player = PlayerHandle::spawn(
+            ctx.clone(),
+            manager,
+            self.settings.clone(),
+            self.cache.clone(),
+            guild_id,
+        );
+        players.insert(guild_id, player.clone());
+        player
+    }
 }
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{GuildId, RoleId};
+use std::{
+    collections::HashMap,
+    env, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+pub const DEFAULT_SETTINGS_PATH: &str = "guild_settings.json";
+pub const DEFAULT_VOLUME: u16 = 100;
+pub const MAX_VOLUME: u16 = 200;
+pub const MAX_CROSSFADE_SECONDS: u64 = 12;
+pub const DEFAULT_SKIP_THRESHOLD: u8 = 50;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Playback volume in percent, where 100 is ffmpeg's native level.
+    pub volume: u16,
+    pub normalize: bool,
+    pub crossfade_seconds: u64,
+    pub vote_skip: bool,
+    pub skip_threshold: u8,
+    /// Role needed for skipping without a vote, leaving, clearing and changing
+    /// playback settings. Everyone has those rights while it's unset.
+    pub dj_role: Option<RoleId>,
+    pub stay_in_voice: bool,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        GuildSettings {
+            volume: DEFAULT_VOLUME,
+            normalize: false,
+            crossfade_seconds: 0,
+            vote_skip: false,
+            skip_threshold: DEFAULT_SKIP_THRESHOLD,
+            dj_role: None,
+            stay_in_voice: false,
+        }
+    }
+}
+
+impl GuildSettings {
+    pub fn track_volume(&self) -> f32 {
+        f32::from(self.volume) / 100.0
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        Duration::from_secs(self.crossfade_seconds.min(MAX_CROSSFADE_SECONDS))
+    }
+}
+
+pub struct SettingsStore {
+    path: PathBuf,
+    guilds: Mutex<HashMap<GuildId, GuildSettings>>,
+}
+
+impl SettingsStore {
+    /// Loads the store from `SETTINGS_PATH`, or `guild_settings.json` when unset.
+    pub fn from_env() -> Self {
+        let path = env::var("SETTINGS_PATH").unwrap_or_else(|_| DEFAULT_SETTINGS_PATH.to_owned());
+        Self::load(path)
+    }
+
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let guilds = match read_settings(&path) {
+            Ok(guilds) => guilds,
+            Err(why) => {
+                if why.kind() != io::ErrorKind::NotFound {
+                    println!("Error reading guild settings: {:?}", why);
+                }
+                HashMap::new()
+            }
+        };
+
+        SettingsStore {
+            path,
+            guilds: Mutex::new(guilds),
+        }
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> GuildSettings {
+        let guilds = self.guilds.lock().await;
+        guilds.get(&guild_id).cloned().unwrap_or_default()
+    }
+
+    pub async fn update(
+        &self,
+        guild_id: GuildId,
+        change: impl FnOnce(&mut GuildSettings),
+    ) -> io::Result<GuildSettings> {
+        let mut guilds = self.guilds.lock().await;
+        let settings = guilds.entry(guild_id).or_default();
+        change(settings);
+        let updated = settings.clone();
+
+        let contents = serde_json::to_vec_pretty(&*guilds)?;
+        let temp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&temp_path, contents).await?;
+        tokio::fs::rename(&temp_path, &self.path).await?;
+
+        Ok(updated)
+    }
+}
+
+fn read_settings(path: &Path) -> io::Result<HashMap<GuildId, GuildSettings>> {
+    let contents = std::fs::read(path)?;
+    serde_json::from_slice(&contents)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuildSettings, SettingsStore, MAX_CROSSFADE_SECONDS};
+    use serenity::model::prelude::GuildId;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn settings_survive_a_reload() {
+        let path =
+            std::env::temp_dir().join(format!("boss-bot-settings-{}.json", std::process::id()));
+        let guild_id = GuildId::new(42);
+
+        let store = SettingsStore::load(&path);
+        assert_eq!(store.get(guild_id).await, GuildSettings::default());
+        store
+            .update(guild_id, |settings| settings.volume = 35)
+            .await
+            .unwrap();
+
+        let reloaded = SettingsStore::load(&path);
+        assert_eq!(reloaded.get(guild_id).await.volume, 35);
+        assert!(!reloaded.get(guild_id).await.normalize);
+        assert_eq!(
+            reloaded.get(GuildId::new(7)).await,
+            GuildSettings::default()
+        );
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn crossfade_is_capped() {
+        let settings = GuildSettings {
+            crossfade_seconds: 60,
+            ..GuildSettings::default()
+        };
+        assert_eq!(
+            settings.crossfade(),
+            Duration::from_secs(MAX_CROSSFADE_SECONDS)
+        );
+        assert!(GuildSettings::default().crossfade().is_zero());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::resources::*;
+use crate::systems::{voice_listeners, PlayerCommand};
+use crate::Handler;
+use serenity::{
+    model::{prelude::GuildId, voice::VoiceState},
+    prelude::Context,
+};
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+/// Tracks how long the bot has been alone or silent in voice, so the player can
+/// leave after `AUTO_LEAVE_EMPTY_SECONDS` alone or `AUTO_LEAVE_IDLE_MINUTES` silent.
+/// A zero limit turns that check off.
+#[derive(Debug, Clone)]
+pub struct AutoLeave {
+    empty_timeout: Duration,
+    idle_timeout: Duration,
+    empty_since: Option<Instant>,
+    idle_since: Option<Instant>,
+}
+
+impl AutoLeave {
+    pub fn new(empty_timeout: Duration, idle_timeout: Duration) -> Self {
+        AutoLeave {
+            empty_timeout,
+            idle_timeout,
+            empty_since: None,
+            idle_since: None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let setting = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            Duration::from_secs(setting(
+                "AUTO_LEAVE_EMPTY_SECONDS",
+                DEFAULT_AUTO_LEAVE_EMPTY_SECONDS,
+            )),
+            Duration::from_secs(
+                setting("AUTO_LEAVE_IDLE_MINUTES", DEFAULT_AUTO_LEAVE_IDLE_MINUTES) * 60,
+            ),
+        )
+    }
+
+    pub fn listeners_changed(&mut self, listeners: usize, now: Instant) {
+        if listeners == 0 {
+            self.empty_since.get_or_insert(now);
+        } else {
+            self.empty_since = None;
+        }
+    }
+
+    pub fn playing_changed(&mut self, playing: bool, now: Instant) {
+        if playing {
+            self.idle_since = None;
+        } else {
+            self.idle_since.get_or_insert(now);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.empty_since = None;
+        self.idle_since = None;
+    }
+
+    pub fn due(&self, now: Instant) -> Option<String> {
+        let expired = |since: Option<Instant>, timeout: Duration| {
+            !timeout.is_zero() && since.is_some_and(|since| now.duration_since(since) >= timeout)
+        };
+
+        if expired(self.empty_since, self.empty_timeout) {
+            Some("Leaving, everyone else left the voice channel".to_owned())
+        } else if expired(self.idle_since, self.idle_timeout) {
+            Some(format!(
+                "Leaving after {} minutes of silence",
+                self.idle_timeout.as_secs() / 60
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+pub async fn voice_state_changed(
+    app: &Handler,
+    ctx: &Context,
+    old: Option<VoiceState>,
+    new: VoiceState,
+) {
+    let Some(guild_id) = new
+        .guild_id
+        .or_else(|| old.as_ref().and_then(|old| old.guild_id))
+    else {
+        return;
+    };
+    let Some(player) = app.existing_player(guild_id).await else {
+        return;
+    };
+
+    if new.user_id == ctx.cache.current_user().id {
+        player.send(PlayerCommand::VoiceChannelChanged {
+            channel: new.channel_id,
+        });
+    }
+    player.send(PlayerCommand::ListenersChanged {
+        listeners: voice_listeners(ctx, guild_id).len(),
+    });
+}
+
+pub async fn toggle_stay_in_voice(app: &Handler, guild_id: GuildId) -> String {
+    match app
+        .settings
+        .update(guild_id, |settings| {
+            settings.stay_in_voice = !settings.stay_in_voice
+        })
+        .await
+    {
+        Ok(settings) if settings.stay_in_voice => {
+            "24/7 mode on, I'll stay in voice until someone uses `!leave`".to_owned()
+        }
+        Ok(_) => "24/7 mode off, I'll leave when the channel is empty or idle".to_owned(),
+        Err(why) => {
+            println!("Error saving guild settings: {:?}", why);
+            format!("Couldn't save 24/7 mode: {}", why)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AutoLeave;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn leaves_once_alone_for_the_empty_timeout() {
+        let start = Instant::now();
+        let mut auto_leave = AutoLeave::new(Duration::from_secs(60), Duration::from_secs(300));
+        auto_leave.playing_changed(true, start);
+        auto_leave.listeners_changed(0, start);
+        assert!(auto_leave.due(start + Duration::from_secs(59)).is_none());
+        assert!(auto_leave.due(start + Duration::from_secs(60)).is_some());
+
+        auto_leave.listeners_changed(2, start + Duration::from_secs(30));
+        assert!(auto_leave.due(start + Duration::from_secs(90)).is_none());
+    }
+
+    #[test]
+    fn leaves_once_idle_for_the_idle_timeout() {
+        let start = Instant::now();
+        let mut auto_leave = AutoLeave::new(Duration::from_secs(60), Duration::from_secs(300));
+        auto_leave.listeners_changed(3, start);
+        auto_leave.playing_changed(false, start);
+        // Repeated idle reports keep the original start time.
+        auto_leave.playing_changed(false, start + Duration::from_secs(200));
+        assert_eq!(
+            auto_leave.due(start + Duration::from_secs(300)).as_deref(),
+            Some("Leaving after 5 minutes of silence")
+        );
+
+        auto_leave.playing_changed(true, start + Duration::from_secs(301));
+        assert!(auto_leave.due(start + Duration::from_secs(400)).is_none());
+    }
+
+    #[test]
+    fn zero_timeouts_never_leave() {
+        let start = Instant::now();
+        let mut auto_leave = AutoLeave::new(Duration::ZERO, Duration::ZERO);
+        auto_leave.listeners_changed(0, start);
+        auto_leave.playing_changed(false, start);
+        assert!(auto_leave
+            .due(start + Duration::from_secs(86_400))
+            .is_none());
+    }
+}
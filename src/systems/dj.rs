@@ -0,0 +1,152 @@
+use crate::settings::GuildSettings;
+use crate::Handler;
+use serenity::{
+    model::{
+        channel::Message,
+        prelude::{GuildId, RoleId},
+        Permissions,
+    },
+    prelude::Context,
+};
+
+/// Holders of the guild's DJ role, plus server moderators so nobody gets locked out.
+pub fn is_dj(ctx: &Context, msg: &Message, settings: &GuildSettings) -> bool {
+    if is_moderator(ctx, msg) {
+        return true;
+    }
+    let Some(dj_role) = settings.dj_role else {
+        return false;
+    };
+
+    match &msg.member {
+        Some(member) => member.roles.contains(&dj_role),
+        None => msg.guild_id.is_some_and(|guild_id| {
+            ctx.cache.guild(guild_id).is_some_and(|guild| {
+                guild
+                    .members
+                    .get(&msg.author.id)
+                    .is_some_and(|member| member.roles.contains(&dj_role))
+            })
+        }),
+    }
+}
+
+/// Whether the author may use DJ commands; everyone can until a DJ role is set.
+pub fn has_dj_rights(ctx: &Context, msg: &Message, settings: &GuildSettings) -> bool {
+    settings.dj_role.is_none() || is_dj(ctx, msg, settings)
+}
+
+pub async fn require_dj(app: &Handler, ctx: &Context, msg: &Message, guild_id: GuildId) -> bool {
+    let settings = app.settings.get(guild_id).await;
+    if has_dj_rights(ctx, msg, &settings) {
+        return true;
+    }
+
+    let role = settings
+        .dj_role
+        .and_then(|role_id| role_name(ctx, guild_id, role_id))
+        .unwrap_or_else(|| "DJ".to_owned());
+    let refusal = format!("🚫 Only people with the **{}** role can do that.", role);
+    if let Err(why) = msg.channel_id.say(&ctx.http, refusal).await {
+        println!("Error sending DJ refusal: {:?}", why);
+    }
+    false
+}
+
+/// Handles `!djrole [@role|off]`, which only server managers may change.
+pub async fn dj_role(app: &Handler, args: &str, msg: Message, guild_id: GuildId, ctx: &Context) {
+    let args = args.trim();
+    let reply = if args.is_empty() {
+        match app.settings.get(guild_id).await.dj_role {
+            Some(role_id) => format!(
+                "The DJ role is **{}**",
+                role_name(ctx, guild_id, role_id).unwrap_or_else(|| role_id.to_string())
+            ),
+            None => "No DJ role is set, so everyone can use every command".to_owned(),
+        }
+    } else if !can_manage_guild(ctx, &msg) {
+        "🚫 Only people who can manage the server can change the DJ role.".to_owned()
+    } else {
+        let role = match args {
+            "off" | "none" => Ok(None),
+            _ => parse_role_id(args)
+                .or_else(|| role_by_name(ctx, guild_id, args))
+                .map(Some)
+                .ok_or("Expected format: `!djrole @role`, `!djrole <role name>` or `!djrole off`"),
+        };
+        match role {
+            Ok(role) => match app
+                .settings
+                .update(guild_id, |settings| settings.dj_role = role)
+                .await
+            {
+                Ok(_) => match role {
+                    Some(role_id) => format!(
+                        "DJ role set to **{}**",
+                        role_name(ctx, guild_id, role_id).unwrap_or_else(|| role_id.to_string())
+                    ),
+                    None => "DJ role cleared, everyone can use every command".to_owned(),
+                },
+                Err(why) => {
+                    println!("Error saving guild settings: {:?}", why);
+                    format!("Couldn't save the DJ role: {}", why)
+                }
+            },
+            Err(message) => message.to_owned(),
+        }
+    };
+
+    if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+        println!("Error sending DJ role reply: {:?}", why);
+    }
+}
+
+fn is_moderator(ctx: &Context, msg: &Message) -> bool {
+    msg.author_permissions(&ctx.cache)
+        .is_some_and(|permissions| permissions.contains(Permissions::MANAGE_CHANNELS))
+}
+
+fn can_manage_guild(ctx: &Context, msg: &Message) -> bool {
+    msg.author_permissions(&ctx.cache)
+        .is_some_and(|permissions| permissions.contains(Permissions::MANAGE_GUILD))
+}
+
+fn role_name(ctx: &Context, guild_id: GuildId, role_id: RoleId) -> Option<String> {
+    let guild = ctx.cache.guild(guild_id)?;
+    guild.roles.get(&role_id).map(|role| role.name.clone())
+}
+
+fn role_by_name(ctx: &Context, guild_id: GuildId, name: &str) -> Option<RoleId> {
+    let guild = ctx.cache.guild(guild_id)?;
+    guild
+        .roles
+        .values()
+        .find(|role| role.name.eq_ignore_ascii_case(name))
+        .map(|role| role.id)
+}
+
+fn parse_role_id(arg: &str) -> Option<RoleId> {
+    let id = arg
+        .strip_prefix("<@&")
+        .and_then(|mention| mention.strip_suffix('>'))
+        .unwrap_or(arg);
+    id.parse::<u64>()
+        .ok()
+        .filter(|id| *id != 0)
+        .map(RoleId::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_role_id;
+    use serenity::model::prelude::RoleId;
+
+    #[test]
+    fn parses_role_mentions_and_ids() {
+        assert_eq!(parse_role_id("<@&1234>"), Some(RoleId::new(1234)));
+        assert_eq!(parse_role_id("1234"), Some(RoleId::new(1234)));
+        assert_eq!(parse_role_id("<@1234>"), None);
+        assert_eq!(parse_role_id("0"), None);
+        assert_eq!(parse_role_id("DJ"), None);
+    }
+}
@@ -1,51 +1,18 @@
 use crate::resources::*;
+use crate::systems::queue_time_remaining;
 use crate::utils::*;
-use crate::Handler;
 use base64::{engine::general_purpose, Engine as _};
 use openai_rust::{chat::ChatArguments, chat::Message as OpenAiMessage, Client as OpenAiClient};
 use reqwest::Client;
 use serde_json::Value;
 use serenity::{
     builder::{CreateAttachment, CreateMessage},
-    model::{
-        channel::Message,
-        prelude::{GuildId, ReactionType},
-    },
+    model::{channel::Message, prelude::ReactionType},
     prelude::Context,
 };
-use songbird::Songbird;
-use std::{
-    collections::VecDeque,
-    env,
-    sync::{atomic::Ordering, Arc},
-};
+use std::{collections::VecDeque, env};
 use tokio::time::{sleep, Duration, Instant};
 
-pub async fn skip_all_enabled(app: &Handler, guild_id: GuildId, manager: Arc<Songbird>) {
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-        handler.stop();
-    }
-    {
-        let playing_lock = app.playing.lock().await;
-        if *playing_lock {
-            app.skip_player.store(true, Ordering::SeqCst);
-        }
-    }
-    {
-        let tracking_lock = app.tracking.lock().await;
-        if *tracking_lock {
-            app.skip_tracker.store(true, Ordering::SeqCst);
-        }
-    }
-    {
-        let looping_lock = app.looping.lock().await;
-        if *looping_lock {
-            app.skip_loop.store(true, Ordering::SeqCst);
-        }
-    }
-}
-
 pub async fn chat_gpt(api_key: &str, prompt: &str) -> String {
     let client: OpenAiClient = OpenAiClient::new(api_key);
     let model = env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-5.6-sol".to_owned());
@@ -189,6 +156,7 @@ pub async fn say_queue(
     msg: Message,
     ctx: &Context,
     current_song: Option<Node>,
+    position: Duration,
     queue: VecDeque<Node>,
 ) {
     let mut name_str = String::from("🎵 **Queue** 🎵\n```markdown\n");
@@ -196,7 +164,7 @@ pub async fn say_queue(
 
     if let Some(current_song) = current_song.as_ref() {
         has_content = true;
-        let final_title = queue_title(current_song).await;
+        let final_title = queue_title(current_song);
         name_str.push_str(&format!("Currently playing: {}\n", final_title));
 
         if !queue.is_empty() {
@@ -206,9 +174,16 @@ pub async fn say_queue(
 
     for (index, item) in queue.iter().enumerate() {
         has_content = true;
-        let final_title = queue_title(item).await;
+        let final_title = queue_title(item);
         name_str.push_str(&format!("{}: {}\n", index + 1, final_title));
     }
+    let remaining = queue_time_remaining(current_song.as_ref(), position, &queue);
+    if !remaining.is_zero() {
+        name_str.push_str(&format!(
+            "\nTime remaining: {}\n",
+            format_total_duration(remaining)
+        ));
+    }
     name_str.push_str("```");
 
     if has_content {
@@ -220,12 +195,10 @@ pub async fn say_queue(
     }
 }
 
-async fn queue_title(item: &Node) -> String {
-    match get_video_title(&item.url).await {
-        Ok(title) => title.trim().to_owned(),
-        Err(why) => {
-            println!("Error getting queue video title: {:?}", why);
-            format!("{} (title unavailable)", item.url)
-        }
+fn queue_title(item: &Node) -> String {
+    if item.is_live() {
+        format!("{} (LIVE)", item.title())
+    } else {
+        item.title().to_owned()
     }
 }
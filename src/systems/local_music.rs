@@ -0,0 +1,109 @@
+use crate::library::library_page;
+use crate::resources::*;
+use crate::systems::{added_tracks_summary, author_voice_channel, PlayerCommand};
+use crate::Handler;
+use serenity::{
+    model::{channel::Message, prelude::GuildId},
+    prelude::Context,
+};
+
+pub async fn local_music(
+    app: &Handler,
+    args: &str,
+    msg: Message,
+    guild_id: GuildId,
+    ctx: &Context,
+) -> bool {
+    if !app.library.is_configured() {
+        let _ = msg
+            .reply(ctx, "No local library here, set `MUSIC_DIR` first.")
+            .await;
+        return false;
+    }
+
+    let args = args.trim();
+    if args.is_empty() {
+        let _ = msg
+            .reply(
+                ctx,
+                "Expected format: `!local <search>` or `!local list [page]`",
+            )
+            .await;
+        return false;
+    }
+
+    if let Some(page) = list_page(args) {
+        let reply = match page.and_then(|page| library_page(app.library.tracks(), page)) {
+            Some(listing) => listing,
+            None => "No such page".to_string(),
+        };
+        let _ = msg.channel_id.say(&ctx.http, reply).await;
+        return false;
+    }
+
+    let matches = app.library.search(args);
+    if matches.is_empty() {
+        let _ = msg
+            .reply(ctx, format!("Nothing in the library matches `{}`", args))
+            .await;
+        return false;
+    }
+
+    let Some(channel) = author_voice_channel(ctx, &msg, guild_id).await else {
+        return false;
+    };
+    if let Err(why) = msg.delete(ctx).await {
+        println!("Error deleting message: {:?}", why);
+    }
+
+    let nodes: Vec<Node> = matches
+        .iter()
+        .take(MAX_PLAYLIST_TRACKS)
+        .map(|track| Node::from_metadata(track.url(), track.metadata()).requested_by(msg.author.id))
+        .collect();
+    let mut summary =
+        added_tracks_summary(nodes.len(), nodes.iter().map(|node| node.duration).sum());
+    if matches.len() > nodes.len() {
+        summary.push_str(&format!(" out of {} matches", matches.len()));
+    }
+    let _ = msg.channel_id.say(&ctx.http, summary).await;
+
+    app.player(ctx, guild_id)
+        .await
+        .send(PlayerCommand::Enqueue {
+            nodes,
+            voice_channel: channel,
+            text_channel: msg.channel_id,
+            play_next: false,
+        });
+    true
+}
+
+/// `Some(page)` for `list`/`list <n>`, with `None` inside for an unparseable page.
+fn list_page(args: &str) -> Option<Option<usize>> {
+    let rest = args.strip_prefix("list")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let rest = rest.trim();
+    Some(if rest.is_empty() {
+        Some(1)
+    } else {
+        rest.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::list_page;
+
+    #[test]
+    fn parses_list_pages() {
+        assert_eq!(list_page("list"), Some(Some(1)));
+        assert_eq!(list_page("list 3"), Some(Some(3)));
+        assert_eq!(list_page("list two"), Some(None));
+        assert_eq!(list_page("listen to this"), None);
+        assert_eq!(list_page("daft punk"), None);
+    }
+}
@@ -1,22 +1,24 @@
-use crate::resources::*;
-use crate::systems::tracker;
+use crate::systems::{manage_queue, RepeatCommand, RepeatMode};
 use crate::utils::*;
 use crate::Handler;
-use serenity::{model::channel::Message, prelude::Context};
-use std::{sync::atomic::Ordering, time::Duration};
-use tokio::time::sleep;
+use serenity::{
+    model::{channel::Message, prelude::GuildId},
+    prelude::Context,
+};
 
+/// `!loop <count> <url>` queues the song with `count - 1` repeats attached.
 pub async fn loop_song(
     app: &Handler,
     full_message: &str,
     msg: Message,
+    guild_id: GuildId,
     ctx: &Context,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let url = match extract_youtube_url(full_message) {
+) {
+    let url = match extract_source_url(full_message) {
         Ok(url) => url,
         Err(_) => {
             let _ = msg.reply(ctx, "Bad URL").await;
-            return Ok(());
+            return;
         }
     };
 
@@ -24,191 +26,39 @@ pub async fn loop_song(
         Ok(count) => count,
         Err(message) => {
             let _ = msg.channel_id.say(&ctx.http, message).await;
-            return Ok(());
-        }
-    };
-
-    let already_looping = {
-        let mut looping_lock = app.looping.lock().await;
-        if *looping_lock {
-            true
-        } else {
-            *looping_lock = true;
-            false
-        }
-    };
-
-    if already_looping {
-        let _ = msg.channel_id.say(&ctx.http, "You loopin rn").await;
-        return Ok(());
-    }
-
-    let result = run_loop_song(app, url, count, msg, ctx).await;
-
-    {
-        let mut looping_lock = app.looping.lock().await;
-        *looping_lock = false;
-    }
-
-    result
-}
-
-async fn run_loop_song(
-    app: &Handler,
-    url: &str,
-    count: usize,
-    msg: Message,
-    ctx: &Context,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let is_playing = *app.playing.lock().await;
-    if is_playing {
-        let _ = msg
-            .channel_id
-            .say(&ctx.http, "not loopin til queue done")
-            .await;
-        println!("not looping while music queue running");
-        return Ok(());
-    }
-
-    let Some(guild_id) = msg.guild_id else {
-        let _ = msg
-            .reply(ctx, "I only handle loop commands inside a server.")
-            .await;
-        return Ok(());
-    };
-
-    let channel_id = {
-        let Some(guild) = ctx.cache.guild(guild_id) else {
-            let _ = msg
-                .reply(
-                    ctx,
-                    "I couldn't find this server in cache. Try again in a moment.",
-                )
-                .await;
-            return Ok(());
-        };
-
-        guild
-            .voice_states
-            .get(&msg.author.id)
-            .and_then(|voice_state| voice_state.channel_id)
-    };
-
-    let Some(channel_id) = channel_id else {
-        let _ = msg.reply(ctx, "Join voice noob").await;
-        return Ok(());
-    };
-
-    let duration = match get_video_duration(url).await {
-        Ok(duration) => duration,
-        Err(why) => {
-            println!("Error getting video duration: {:?}", why);
-            let _ = msg
-                .channel_id
-                .say(
-                    &ctx.http,
-                    format!("Couldn't inspect that YouTube URL: {}", why),
-                )
-                .await;
-            return Ok(());
+            return;
         }
     };
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialization.")
-        .clone();
-    if let Err(why) = manager.join(guild_id, channel_id).await {
-        println!("Failed to join voice: {:?}", why);
+    if manage_queue(url, msg.clone(), guild_id, ctx, app, false, count - 1).await {
         let _ = msg
             .channel_id
             .say(
                 &ctx.http,
-                format!("Couldn't join your voice channel: {}", why),
+                format!("looping {} times for my king {}", count, msg.author),
             )
             .await;
-        return Ok(());
     }
+}
 
-    let Some(handler_lock) = manager.get(guild_id) else {
-        let _ = msg
-            .channel_id
-            .say(&ctx.http, "Failed to prepare voice playback.")
-            .await;
-        return Ok(());
-    };
-
-    let _ = msg
-        .channel_id
-        .say(
-            &ctx.http,
-            format!("looping {} times for my king {}", count, msg.author.clone()),
-        )
-        .await;
-
-    let mut iterations = 0;
-    loop {
-        let source = match create_youtube_audio_input(url) {
-            Ok(source) => source,
-            Err(why) => return Err(Box::new(why)),
-        };
-
-        let ctx_clone = ctx.clone();
-        let tracker_clone = app.tracking.clone();
-        let skip_tracker_clone = app.skip_tracker.clone();
-        let msg_clone = msg.clone();
-        let node = Node::from(url.to_string(), duration);
-
-        let track_handle = {
-            let mut handler = handler_lock.lock().await;
-            handler.play_only_input(source)
-        };
-
-        if let Err(why) = track_handle.make_playable_async().await {
-            return Err(Box::new(why));
-        }
-
-        {
-            let mut current_song = app.current_song.lock().await;
-            *current_song = Some(node.clone());
-        }
-
-        tokio::spawn(async move {
-            tracker(
-                ctx_clone,
-                skip_tracker_clone,
-                tracker_clone,
-                msg_clone,
-                node,
-            )
-            .await;
-        });
-
-        tokio::select! {
-            _ = sleep(duration + Duration::from_secs(1)) => {
-                iterations += 1;
-                if iterations >= count {
-                    let mut current_song = app.current_song.lock().await;
-                    *current_song = None;
-                    return Ok(());
-                }
-            }
-            _ = wait_for_loop_skip(app) => {
-                let is_tracking = *app.tracking.lock().await;
-                if is_tracking {
-                    app.skip_tracker.store(true, Ordering::SeqCst);
-                    wait_for_tracker_to_stop(app).await;
-                }
-                let mut current_song = app.current_song.lock().await;
-                *current_song = None;
-                return Ok(());
-            }
-        }
+pub fn parse_repeat_command(args: &str) -> Result<RepeatCommand, &'static str> {
+    let mut parts = args.split_whitespace();
+    let (mode, count) = (parts.next(), parts.next());
+    if parts.next().is_some() {
+        return Err("Expected format: `!repeat off|one|all` or `!repeat one <count>`");
+    }
 
-        {
-            let mut current_song = app.current_song.lock().await;
-            *current_song = None;
-        }
+    match (mode, count) {
+        (Some("off"), None) => Ok(RepeatCommand::Mode(RepeatMode::Off)),
+        (Some("one" | "track"), None) => Ok(RepeatCommand::Mode(RepeatMode::Track)),
+        (Some("all" | "queue"), None) => Ok(RepeatCommand::Mode(RepeatMode::Queue)),
+        (Some("one" | "track"), Some(count)) | (Some(count), None) => count
+            .parse::<usize>()
+            .ok()
+            .filter(|count| *count > 0)
+            .map(RepeatCommand::Times)
+            .ok_or("Repeat count must be a positive whole number."),
+        _ => Err("Expected format: `!repeat off|one|all` or `!repeat one <count>`"),
     }
 }
 
@@ -229,28 +79,10 @@ fn parse_loop_count(message: &str, url: &str) -> Result<usize, &'static str> {
     Ok(count)
 }
 
-async fn wait_for_loop_skip(app: &Handler) {
-    loop {
-        sleep(Duration::from_millis(100)).await;
-        if app.skip_loop.load(Ordering::SeqCst) {
-            app.skip_loop.store(false, Ordering::SeqCst);
-            break;
-        }
-    }
-}
-
-async fn wait_for_tracker_to_stop(app: &Handler) {
-    for _ in 0..100 {
-        if !*app.tracking.lock().await {
-            return;
-        }
-        sleep(Duration::from_millis(50)).await;
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use super::parse_loop_count;
+    use super::{parse_loop_count, parse_repeat_command};
+    use crate::systems::{RepeatCommand, RepeatMode};
 
     const URL: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
 
@@ -265,4 +97,25 @@ mod tests {
         assert!(parse_loop_count(&format!("-1 {URL}"), URL).is_err());
         assert!(parse_loop_count(URL, URL).is_err());
     }
+
+    #[test]
+    fn parses_repeat_modes_and_counts() {
+        assert_eq!(
+            parse_repeat_command("off"),
+            Ok(RepeatCommand::Mode(RepeatMode::Off))
+        );
+        assert_eq!(
+            parse_repeat_command("one"),
+            Ok(RepeatCommand::Mode(RepeatMode::Track))
+        );
+        assert_eq!(
+            parse_repeat_command("all"),
+            Ok(RepeatCommand::Mode(RepeatMode::Queue))
+        );
+        assert_eq!(parse_repeat_command("one 3"), Ok(RepeatCommand::Times(3)));
+        assert_eq!(parse_repeat_command("2"), Ok(RepeatCommand::Times(2)));
+        assert!(parse_repeat_command("one 0").is_err());
+        assert!(parse_repeat_command("sometimes").is_err());
+        assert!(parse_repeat_command("").is_err());
+    }
 }
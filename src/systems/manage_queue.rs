@@ -1,190 +1,509 @@
 use crate::resources::*;
-use crate::systems::play_youtube;
+use crate::systems::PlayerCommand;
 use crate::utils::*;
 use crate::Handler;
+use rand::seq::SliceRandom;
 use serenity::{
-    model::{channel::Message, prelude::GuildId},
+    model::{
+        channel::{Attachment, Message},
+        prelude::{ChannelId, GuildId, ReactionType, UserId},
+    },
     prelude::Context,
 };
-use songbird::Songbird;
-use std::{
-    sync::{atomic::Ordering, Arc},
-    time::Duration,
-};
-use tokio::time::sleep;
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::{sleep, Instant};
 
 pub async fn manage_queue(
     message: &str,
     msg: Message,
     guild_id: GuildId,
     ctx: &Context,
-    manager: Arc<Songbird>,
     app: &Handler,
-) {
-    match extract_youtube_url(message) {
+    play_next: bool,
+    repeats: usize,
+) -> bool {
+    match extract_source_url(message) {
         Ok(url) => {
             if let Err(why) = msg.delete(ctx).await {
                 println!("Error deleting message: {:?}", why);
             }
 
-            let is_looping = *app.looping.lock().await;
-            if is_looping {
-                let _ = msg.channel_id.say(&ctx.http, "You loopin rn").await;
-                return;
-            }
-
-            let channel_id = {
-                let Some(guild) = ctx.cache.guild(guild_id) else {
-                    let _ = msg
-                        .reply(
-                            ctx,
-                            "I couldn't find this server in cache. Try again in a moment.",
-                        )
-                        .await;
-                    return;
-                };
-
-                guild
-                    .voice_states
-                    .get(&msg.author.id)
-                    .and_then(|voice_state| voice_state.channel_id)
+            let Some(channel) = author_voice_channel(ctx, &msg, guild_id).await else {
+                return false;
             };
 
-            let Some(channel) = channel_id else {
-                let _ = msg.reply(ctx, "Join voice noob").await;
-                return;
+            let playlist_id = match youtube_playlist_page_id(url) {
+                Some(playlist_id) => Some(playlist_id),
+                None => match youtube_video_playlist_id(url) {
+                    Some(playlist_id) if confirm_playlist(ctx, &msg).await => Some(playlist_id),
+                    _ => None,
+                },
             };
 
-            let duration: Duration = match get_video_duration(url).await {
-                Ok(duration) => duration,
-                Err(why) => {
-                    println!("Error getting video duration: {:?}", why);
+            let nodes = match playlist_id {
+                Some(playlist_id) => {
+                    let playlist_url = youtube_playlist_url(playlist_id);
+                    let entries =
+                        match get_playlist_entries(&playlist_url, MAX_PLAYLIST_TRACKS).await {
+                            Ok(entries) if !entries.is_empty() => entries,
+                            Ok(_) => {
+                                let _ = msg
+                                    .channel_id
+                                    .say(&ctx.http, "That playlist has no playable videos.")
+                                    .await;
+                                return false;
+                            }
+                            Err(why) => {
+                                println!("Error listing playlist: {:?}", why);
+                                let _ = msg
+                                    .channel_id
+                                    .say(&ctx.http, format!("Couldn't read that playlist: {}", why))
+                                    .await;
+                                return false;
+                            }
+                        };
+
                     let _ = msg
                         .channel_id
-                        .say(
-                            &ctx.http,
-                            format!("Couldn't inspect that YouTube URL: {}", why),
-                        )
+                        .say(&ctx.http, playlist_summary(&entries))
                         .await;
-                    return;
+
+                    entries
+                        .into_iter()
+                        .map(|entry| Node {
+                            repeats,
+                            ..Node::from_metadata(entry.webpage_url.clone(), entry)
+                                .requested_by(msg.author.id)
+                        })
+                        .collect()
                 }
-            };
+                None => {
+                    let metadata = match get_source_metadata(url).await {
+                        Ok(metadata) => metadata,
+                        Err(why) => {
+                            println!("Error getting source metadata: {:?}", why);
+                            let _ = msg
+                                .channel_id
+                                .say(&ctx.http, format!("Couldn't inspect that URL: {}", why))
+                                .await;
+                            return false;
+                        }
+                    };
 
-            let should_drive_queue = {
-                let mut queue = VIDEO_QUEUE.lock().await;
-                queue.push_back(Node::from(url.to_string(), duration));
-                let mut playing = app.playing.lock().await;
-                if *playing {
-                    false
-                } else {
-                    *playing = true;
-                    true
+                    vec![Node {
+                        repeats,
+                        ..Node::from_metadata(url.to_string(), metadata)
+                            .starting_at(parse_start_offset(url))
+                            .requested_by(msg.author.id)
+                    }]
                 }
             };
 
-            if !should_drive_queue {
-                return;
-            }
+            app.player(ctx, guild_id)
+                .await
+                .send(PlayerCommand::Enqueue {
+                    nodes,
+                    voice_channel: channel,
+                    text_channel: msg.channel_id,
+                    play_next,
+                });
+            true
+        }
+        Err(_) => {
+            let _ = msg.reply(ctx, "Bad URL").await;
+            false
+        }
+    }
+}
 
-            if let Err(why) = manager.join(guild_id, channel).await {
-                println!("Failed to join voice: {:?}", why);
-                {
-                    let mut queue = VIDEO_QUEUE.lock().await;
-                    queue.clear();
-                }
-                {
-                    let mut playing = app.playing.lock().await;
-                    *playing = false;
-                }
+pub async fn queue_attachments(
+    msg: Message,
+    guild_id: GuildId,
+    ctx: &Context,
+    app: &Handler,
+) -> bool {
+    // The message stays put: deleting it would also delete the files we're about to stream.
+    let Some(channel) = author_voice_channel(ctx, &msg, guild_id).await else {
+        return false;
+    };
+
+    let mut nodes = Vec::new();
+    for attachment in &msg.attachments {
+        let Some(extension) = audio_file_extension(&attachment.filename) else {
+            continue;
+        };
+        let extension = extension.to_string();
+
+        match attachment_metadata(attachment, extension).await {
+            Ok(metadata) => {
+                nodes.push(
+                    Node::from_metadata(attachment.url.clone(), metadata)
+                        .requested_by(msg.author.id),
+                );
+            }
+            Err(why) => {
+                println!(
+                    "Error reading attachment {}: {:?}",
+                    attachment.filename, why
+                );
                 let _ = msg
-                    .channel_id
-                    .say(
-                        &ctx.http,
-                        format!("Couldn't join your voice channel: {}", why),
+                    .reply(
+                        ctx,
+                        format!("Couldn't read `{}`: {}", attachment.filename, why),
                     )
                     .await;
-                return;
             }
+        }
+    }
 
-            loop {
-                let duration = {
-                    let queue = VIDEO_QUEUE.lock().await;
-                    queue.front().map(|node| node.duration)
-                };
+    if nodes.is_empty() {
+        return false;
+    }
 
-                let Some(the_duration) = duration else {
-                    let queue_has_item = {
-                        let queue = VIDEO_QUEUE.lock().await;
-                        queue.front().is_some()
-                    };
+    app.player(ctx, guild_id)
+        .await
+        .send(PlayerCommand::Enqueue {
+            nodes,
+            voice_channel: channel,
+            text_channel: msg.channel_id,
+            play_next: false,
+        });
+    true
+}
 
-                    if queue_has_item {
-                        continue;
-                    }
+/// Reads an attachment's tags and length from the start of the file, leaving the
+/// full download to playback.
+async fn attachment_metadata(
+    attachment: &Attachment,
+    extension: String,
+) -> std::io::Result<VideoMetadata> {
+    let prefix = download_prefix(&attachment.url, ATTACHMENT_PROBE_BYTES).await?;
+    let total_len = u64::from(attachment.size);
+    let probe = tokio::task::spawn_blocking(move || {
+        probe_audio_prefix(prefix, total_len, Some(&extension))
+    })
+    .await
+    .unwrap_or_else(|error| Err(std::io::Error::other(error)))?;
 
-                    let mut playing = app.playing.lock().await;
-                    *playing = false;
-                    break;
-                };
-
-                let tracker_clone = app.tracking.clone();
-                let skip_tracker_clone = app.skip_tracker.clone();
-                let ctx_clone = ctx.clone();
-                let msg_clone = msg.clone();
-
-                if let Err(why) = play_youtube(
-                    &ctx_clone,
-                    msg_clone.clone(),
-                    skip_tracker_clone,
-                    tracker_clone,
-                    app.current_song.clone(),
+    Ok(VideoMetadata {
+        title: probe.title.unwrap_or_else(|| attachment.filename.clone()),
+        uploader: probe.artist,
+        duration: Some(probe.duration),
+        webpage_url: attachment.url.clone(),
+        ..VideoMetadata::default()
+    })
+}
+
+pub async fn author_voice_channel(
+    ctx: &Context,
+    msg: &Message,
+    guild_id: GuildId,
+) -> Option<ChannelId> {
+    let channel_id = {
+        let Some(guild) = ctx.cache.guild(guild_id) else {
+            let _ = msg
+                .reply(
+                    ctx,
+                    "I couldn't find this server in cache. Try again in a moment.",
                 )
-                .await
-                {
-                    println!("Error playing YouTube audio: {:?}", why);
-                    let _ = msg
-                        .channel_id
-                        .say(&ctx.http, format!("Couldn't play that audio: {}", why))
-                        .await;
-                    continue;
-                }
+                .await;
+            return None;
+        };
 
-                tokio::select! {
-                    _ = sleep(the_duration + Duration::from_secs(1)) => {}
-                    _ = async {
-                        loop {
-                            sleep(Duration::from_millis(100)).await;
-                            if app.skip_player.load(Ordering::SeqCst) {
-                                app.skip_player.store(false, Ordering::SeqCst);
-                                break;
-                            }
-                        }
-                    } => {
-                        let is_tracking = *app.tracking.lock().await;
-                        if is_tracking {
-                            app.skip_tracker.store(true, Ordering::SeqCst);
-                            wait_for_tracker_to_stop(app).await;
-                        }
-                    }
-                }
+        guild
+            .voice_states
+            .get(&msg.author.id)
+            .and_then(|voice_state| voice_state.channel_id)
+    };
+
+    if channel_id.is_none() {
+        let _ = msg.reply(ctx, "Join voice noob").await;
+    }
+    channel_id
+}
+
+/// Anything but a ✅ from the requester within 30 seconds keeps the single video.
+async fn confirm_playlist(ctx: &Context, msg: &Message) -> bool {
+    let prompt = format!(
+        "<@{}> that video is part of a playlist. ✅ queues the playlist (up to {} tracks), ❌ just the video.",
+        msg.author.id, MAX_PLAYLIST_TRACKS
+    );
+    let Ok(bot_msg) = msg.channel_id.say(&ctx.http, prompt).await else {
+        return false;
+    };
 
-                {
-                    let mut current_song = app.current_song.lock().await;
-                    *current_song = None;
+    let choices = ["✅", "❌"];
+    for emoji in choices {
+        if let Err(why) = bot_msg
+            .react(&ctx.http, ReactionType::Unicode(emoji.to_string()))
+            .await
+        {
+            println!("Error adding playlist reactions: {:?}", why);
+        }
+    }
+
+    let mut selected = None;
+    let selection_deadline = Instant::now() + Duration::from_secs(30);
+    while selected.is_none() && Instant::now() < selection_deadline {
+        for emoji in choices {
+            let reaction_type = ReactionType::Unicode(emoji.to_string());
+            if let Ok(users) = bot_msg
+                .reaction_users(&ctx.http, reaction_type, None, None)
+                .await
+            {
+                if users.iter().any(|user| user.id == msg.author.id) {
+                    selected = Some(emoji == "✅");
+                    break;
                 }
             }
         }
-        Err(_) => {
-            let _ = msg.reply(ctx, "Bad URL").await;
+        if selected.is_none() {
+            sleep(Duration::from_secs(1)).await;
         }
     }
+
+    let _ = bot_msg.delete(&ctx).await;
+    selected.unwrap_or(false)
 }
 
-async fn wait_for_tracker_to_stop(app: &Handler) {
-    for _ in 0..100 {
-        if !*app.tracking.lock().await {
-            return;
-        }
-        sleep(Duration::from_millis(50)).await;
+fn playlist_summary(entries: &[VideoMetadata]) -> String {
+    let total = entries.iter().filter_map(|entry| entry.duration).sum();
+    let mut summary = added_tracks_summary(entries.len(), total);
+    if entries.len() >= MAX_PLAYLIST_TRACKS {
+        summary.push_str(&format!(
+            ", playlists are capped at {} tracks",
+            MAX_PLAYLIST_TRACKS
+        ));
+    }
+    summary
+}
+
+pub fn added_tracks_summary(count: usize, total: Duration) -> String {
+    format!(
+        "Added {} track{} ({})",
+        count,
+        if count == 1 { "" } else { "s" },
+        format_total_duration(total)
+    )
+}
+
+pub fn queue_time_remaining(
+    current_song: Option<&Node>,
+    position: Duration,
+    queue: &VecDeque<Node>,
+) -> Duration {
+    let current = current_song
+        .filter(|node| !node.is_live())
+        .map(|node| node.duration.saturating_sub(position))
+        .unwrap_or_default();
+
+    queue
+        .iter()
+        .filter(|node| !node.is_live())
+        .map(Node::remaining)
+        .sum::<Duration>()
+        + current
+}
+
+/// Removes the entry `say_queue` prints as `position` (1-based).
+pub fn remove_queue_entry(queue: &mut VecDeque<Node>, position: usize) -> Option<Node> {
+    queue.remove(position.checked_sub(1)?)
+}
+
+pub fn move_queue_entry(queue: &mut VecDeque<Node>, from: usize, to: usize) -> bool {
+    if to == 0 || to > queue.len() {
+        return false;
+    }
+
+    let Some(node) = remove_queue_entry(queue, from) else {
+        return false;
+    };
+    queue.insert(to - 1, node);
+    true
+}
+
+pub fn skip_queue_to(queue: &mut VecDeque<Node>, position: usize) -> bool {
+    if position == 0 || position > queue.len() {
+        return false;
+    }
+
+    queue.drain(..position - 1);
+    true
+}
+
+/// Whether `!skipto position` is allowed: without DJ rights it may neither skip
+/// a playing song nor drop the songs queued ahead of `position`.
+pub fn may_skip_to(position: usize, playing: bool, is_dj: bool) -> bool {
+    is_dj || (!playing && position <= 1)
+}
+
+pub fn shuffle_queue(queue: &mut VecDeque<Node>) {
+    queue.make_contiguous().shuffle(&mut rand::rng());
+}
+
+pub fn undo_last_request(queue: &mut VecDeque<Node>, user_id: UserId) -> Option<Node> {
+    let index = queue
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| node.requested_by == Some(user_id))
+        .max_by_key(|(_, node)| node.requested_at)
+        .map(|(index, _)| index)?;
+
+    queue.remove(index)
+}
+
+pub fn parse_queue_position(input: &str) -> Option<usize> {
+    input
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|position| *position > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        may_skip_to, move_queue_entry, parse_queue_position, playlist_summary,
+        queue_time_remaining, remove_queue_entry, shuffle_queue, skip_queue_to, undo_last_request,
+    };
+    use crate::resources::Node;
+    use crate::utils::VideoMetadata;
+    use serenity::model::prelude::UserId;
+    use std::{collections::VecDeque, time::Duration};
+
+    fn queue(urls: &[&str]) -> VecDeque<Node> {
+        urls.iter()
+            .map(|url| Node::from(url.to_string(), Duration::from_secs(1)))
+            .collect()
+    }
+
+    fn urls(queue: &VecDeque<Node>) -> Vec<&str> {
+        queue.iter().map(|node| node.url.as_str()).collect()
+    }
+
+    #[test]
+    fn removes_by_printed_position() {
+        let mut entries = queue(&["a", "b", "c"]);
+        assert_eq!(remove_queue_entry(&mut entries, 2).unwrap().url, "b");
+        assert!(remove_queue_entry(&mut entries, 0).is_none());
+        assert!(remove_queue_entry(&mut entries, 3).is_none());
+        assert_eq!(urls(&entries), ["a", "c"]);
+    }
+
+    #[test]
+    fn moves_entries_and_rejects_out_of_range_targets() {
+        let mut entries = queue(&["a", "b", "c"]);
+        assert!(move_queue_entry(&mut entries, 3, 1));
+        assert_eq!(urls(&entries), ["c", "a", "b"]);
+        assert!(!move_queue_entry(&mut entries, 1, 4));
+        assert_eq!(urls(&entries), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn skipping_to_an_entry_drops_the_ones_before_it() {
+        let mut entries = queue(&["a", "b", "c"]);
+        assert!(skip_queue_to(&mut entries, 3));
+        assert_eq!(urls(&entries), ["c"]);
+        assert!(!skip_queue_to(&mut entries, 2));
+    }
+
+    #[test]
+    fn only_djs_can_skip_to_past_a_song() {
+        assert!(!may_skip_to(1, true, false));
+        assert!(!may_skip_to(3, false, false));
+        assert!(may_skip_to(1, false, false));
+        assert!(may_skip_to(3, true, true));
+    }
+
+    #[test]
+    fn queue_positions_are_one_based() {
+        assert_eq!(parse_queue_position(" 2 "), Some(2));
+        assert_eq!(parse_queue_position("0"), None);
+        assert_eq!(parse_queue_position("two"), None);
+    }
+
+    #[test]
+    fn shuffling_keeps_every_entry() {
+        let mut entries = queue(&["a", "b", "c", "d"]);
+        shuffle_queue(&mut entries);
+        let mut shuffled = urls(&entries);
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn undo_removes_the_callers_latest_request() {
+        let alice = UserId::new(1);
+        let bob = UserId::new(2);
+        let mut entries = VecDeque::new();
+        entries.push_back(Node::from("a1".to_owned(), Duration::ZERO).requested_by(alice));
+        entries.push_back(Node::from("b1".to_owned(), Duration::ZERO).requested_by(bob));
+        // `!playnext` puts newer requests at the front.
+        let mut newest = Node::from("a2".to_owned(), Duration::ZERO).requested_by(alice);
+        newest.requested_at += Duration::from_secs(1);
+        entries.push_front(newest);
+
+        assert_eq!(undo_last_request(&mut entries, alice).unwrap().url, "a2");
+        assert_eq!(urls(&entries), ["a1", "b1"]);
+        assert!(undo_last_request(&mut entries, UserId::new(3)).is_none());
+    }
+
+    #[test]
+    fn playlist_summary_reports_count_length_and_cap() {
+        let entry = |secs| VideoMetadata {
+            duration: Some(Duration::from_secs(secs)),
+            ..VideoMetadata::default()
+        };
+        assert_eq!(
+            playlist_summary(&[entry(3600), entry(840)]),
+            "Added 2 tracks (1h14m)"
+        );
+        assert_eq!(playlist_summary(&[entry(65)]), "Added 1 track (1m05s)");
+
+        let capped: Vec<_> = (0..crate::resources::MAX_PLAYLIST_TRACKS)
+            .map(|_| entry(60))
+            .collect();
+        assert!(playlist_summary(&capped).ends_with("capped at 100 tracks"));
+    }
+
+    #[test]
+    fn unknown_lengths_are_not_live() {
+        let unknown = Node::from("probe failed".to_owned(), Duration::ZERO);
+        assert!(!unknown.is_live());
+        assert_eq!(unknown.known_length(), None);
+
+        let mut live = Node::from("radio".to_owned(), Duration::ZERO);
+        live.metadata.is_live = true;
+        assert!(live.is_live());
+        assert_eq!(
+            Node::from("song".to_owned(), Duration::from_secs(60)).known_length(),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn time_remaining_counts_start_offsets_and_skips_live_streams() {
+        let current = Node::from("now".to_owned(), Duration::from_secs(300));
+        let mut queue = VecDeque::new();
+        queue.push_back(
+            Node::from("offset".to_owned(), Duration::from_secs(200))
+                .starting_at(Some(Duration::from_secs(50))),
+        );
+        let mut live = Node::from("live".to_owned(), Duration::ZERO);
+        live.metadata.is_live = true;
+        queue.push_back(live);
+        queue.push_back(
+            Node::from("too far".to_owned(), Duration::from_secs(60))
+                .starting_at(Some(Duration::from_secs(90))),
+        );
+
+        assert_eq!(
+            queue_time_remaining(Some(&current), Duration::from_secs(100), &queue),
+            Duration::from_secs(200 + 150 + 60)
+        );
+        assert_eq!(
+            queue_time_remaining(None, Duration::ZERO, &VecDeque::new()),
+            Duration::ZERO
+        );
     }
 }
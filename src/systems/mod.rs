@@ -1,10 +1,20 @@
+mod local_music;
+pub use local_music::*;
 mod loop_songs;
 pub use loop_songs::*;
 mod manage_queue;
 pub use manage_queue::*;
-mod play_youtube;
-pub use play_youtube::*;
+mod player;
+pub use player::*;
+mod play_source;
+pub use play_source::*;
+mod auto_leave;
+pub use auto_leave::*;
+mod dj;
+pub use dj::*;
 mod general;
 pub use general::*;
 mod tracker;
 pub use tracker::*;
+mod vote_skip;
+pub use vote_skip::*;
@@ -0,0 +1,235 @@
+use crate::cache::AudioCache;
+use crate::resources::*;
+use crate::systems::PlayerCommand;
+use crate::systems::TrackedPlayback;
+use crate::utils::{create_source_input, file_input, ytdlp_audio_input_storing};
+use serenity::async_trait;
+use songbird::{
+    events::{
+        context_data::{DisconnectKind, DisconnectReason},
+        CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent,
+    },
+    input::{
+        codecs::{get_codec_registry, get_probe},
+        Input,
+    },
+    model::CloseCode as VoiceCloseCode,
+    tracks::{PlayMode, Track, TrackHandle},
+    Call,
+};
+use std::{io, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc::UnboundedSender, watch},
+    time::{interval, sleep},
+};
+
+const END_WATCH_INTERVAL: Duration = Duration::from_millis(250);
+const FADE_STEPS_PER_SECOND: f32 = 20.0;
+
+// Songbird fires `End` for errored tracks as well, with the error in `PlayMode::Errored`.
+struct TrackEndForwarder {
+    commands: UnboundedSender<PlayerCommand>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackEndForwarder {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = ctx {
+            for (state, handle) in tracks.iter() {
+                let error = match &state.playing {
+                    PlayMode::Errored(why) => Some(why.to_string()),
+                    _ => None,
+                };
+                let _ = self.commands.send(PlayerCommand::TrackEnded {
+                    track: (*handle).clone(),
+                    error,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+struct DisconnectForwarder {
+    commands: UnboundedSender<PlayerCommand>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for DisconnectForwarder {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::DriverDisconnect(disconnect) = ctx else {
+            return None;
+        };
+        if let Some(command) = disconnect_command(disconnect.kind, disconnect.reason) {
+            let _ = self.commands.send(command);
+        }
+
+        None
+    }
+}
+
+// Failed joins are reported by `join` itself, and leaving or changing channels
+// comes without a reason and is followed through the bot's voice state. A kick
+// (4014) or a requested leave must not be undone by reconnecting.
+fn disconnect_command(
+    kind: DisconnectKind,
+    reason: Option<DisconnectReason>,
+) -> Option<PlayerCommand> {
+    if kind == DisconnectKind::Connect {
+        return None;
+    }
+    match reason? {
+        DisconnectReason::Requested
+        | DisconnectReason::WsClosed(Some(VoiceCloseCode::Disconnected)) => {
+            Some(PlayerCommand::Disconnected)
+        }
+        reason => Some(PlayerCommand::ConnectionLost {
+            reason: format!("{:?}", reason),
+        }),
+    }
+}
+
+pub fn watch_connection(call: &mut Call, commands: UnboundedSender<PlayerCommand>) {
+    // `join` hands back the same call on every rejoin, so replace the old watcher.
+    call.remove_all_global_events();
+    call.add_global_event(
+        Event::Core(CoreEvent::DriverDisconnect),
+        DisconnectForwarder { commands },
+    );
+}
+
+/// Starts `source` alongside anything already playing, so the player has to stop
+/// the previous track itself unless the two are crossfading.
+pub fn play_source(
+    call: &mut Call,
+    source: Input,
+    volume: f32,
+    commands: UnboundedSender<PlayerCommand>,
+) -> Result<TrackHandle, Box<dyn std::error::Error + Send + Sync>> {
+    let track_handle = call.play(Track::new(source).volume(volume));
+    track_handle.add_event(
+        Event::Track(TrackEvent::End),
+        TrackEndForwarder { commands },
+    )?;
+
+    Ok(track_handle)
+}
+
+pub fn create_node_input(
+    cache: &Arc<AudioCache>,
+    node: &Node,
+    start: Duration,
+    filter: Option<&str>,
+) -> io::Result<Input> {
+    if let Some(path) = cache.lookup(node) {
+        match file_input(&path, start, filter) {
+            Ok(source) => return Ok(source),
+            Err(why) => {
+                println!("Error reading cached audio {}: {:?}", path.display(), why);
+                cache.remove(node);
+            }
+        }
+    }
+
+    let Some(partial) = cache.start_store(node) else {
+        return create_source_input(&node.url, start, filter);
+    };
+    let storing = cache.clone();
+    let stored_node = node.clone();
+    ytdlp_audio_input_storing(&node.url, start, filter, &partial, move |stored| {
+        storing.finish_store(&stored_node, stored)
+    })
+    .inspect_err(|why| {
+        cache.finish_store(node, Err(io::Error::new(why.kind(), why.to_string())));
+    })
+}
+
+pub async fn prefetch_source(
+    cache: &Arc<AudioCache>,
+    node: &Node,
+    filter: Option<&str>,
+) -> Result<Input, Box<dyn std::error::Error + Send + Sync>> {
+    let source = create_node_input(cache, node, node.start, filter)?;
+    Ok(source
+        .make_playable_async(get_codec_registry(), get_probe())
+        .await?)
+}
+
+/// Returns the handle and the real time left once `playback` is within `lead` of
+/// the end, or `None` once the player stops following the song.
+pub async fn wait_for_remaining(
+    mut playback: watch::Receiver<TrackedPlayback>,
+    length: Duration,
+    lead: Duration,
+) -> Option<(TrackHandle, Duration)> {
+    let mut ticks = interval(END_WATCH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            changed = playback.changed() => {
+                changed.ok()?;
+            }
+        }
+
+        let tracked = playback.borrow_and_update().clone();
+        let Ok(info) = tracked.handle.get_info().await else {
+            continue;
+        };
+        let remaining = length
+            .saturating_sub(tracked.position(info.position))
+            .div_f64(tracked.speed);
+        if remaining <= lead {
+            return Some((tracked.handle, remaining));
+        }
+    }
+}
+
+pub async fn fade_volume(handle: TrackHandle, from: f32, to: f32, length: Duration) {
+    let steps = (length.as_secs_f32() * FADE_STEPS_PER_SECOND)
+        .ceil()
+        .max(1.0) as u32;
+    for step in 1..=steps {
+        sleep(length / steps).await;
+        let volume = from + (to - from) * step as f32 / steps as f32;
+        if handle.set_volume(volume).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disconnect_command;
+    use crate::systems::PlayerCommand;
+    use songbird::{
+        events::context_data::{DisconnectKind, DisconnectReason},
+        model::CloseCode as VoiceCloseCode,
+    };
+
+    #[test]
+    fn kicks_are_final() {
+        let kicked = DisconnectReason::WsClosed(Some(VoiceCloseCode::Disconnected));
+        assert!(matches!(
+            disconnect_command(DisconnectKind::Runtime, Some(kicked)),
+            Some(PlayerCommand::Disconnected)
+        ));
+        assert!(matches!(
+            disconnect_command(DisconnectKind::Runtime, Some(DisconnectReason::Requested)),
+            Some(PlayerCommand::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn dropped_connections_reconnect() {
+        let expired = DisconnectReason::WsClosed(Some(VoiceCloseCode::SessionTimeout));
+        assert!(matches!(
+            disconnect_command(DisconnectKind::Runtime, Some(expired)),
+            Some(PlayerCommand::ConnectionLost { .. })
+        ));
+        assert!(disconnect_command(DisconnectKind::Runtime, None).is_none());
+        assert!(
+            disconnect_command(DisconnectKind::Connect, Some(DisconnectReason::TimedOut)).is_none()
+        );
+    }
+}
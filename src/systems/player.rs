@@ -0,0 +1,1224 @@
+use crate::audio_filter::{audio_filter_chain, AudioFilter};
+use crate::cache::AudioCache;
+use crate::resources::*;
+use crate::settings::SettingsStore;
+use crate::systems::{
+    channel_listeners, create_node_input, fade_volume, may_skip_to, move_queue_entry, play_source,
+    prefetch_source, remove_queue_entry, shuffle_queue, skip_queue_to, tracker, undo_last_request,
+    votes_needed, wait_for_remaining, watch_connection, AutoLeave, TrackedPlayback,
+};
+use crate::utils::SeekTarget;
+use serenity::{
+    model::prelude::{ChannelId, GuildId, UserId},
+    prelude::Context,
+};
+use songbird::{
+    input::Input,
+    tracks::{PlayMode, TrackHandle},
+    Songbird,
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    env, io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::MissedTickBehavior,
+};
+
+pub enum PlayerCommand {
+    Enqueue {
+        nodes: Vec<Node>,
+        voice_channel: ChannelId,
+        text_channel: ChannelId,
+        play_next: bool,
+    },
+    Repeat {
+        repeat: RepeatCommand,
+        reply: oneshot::Sender<String>,
+    },
+    Skip,
+    VoteSkip {
+        user_id: UserId,
+        /// Everyone who may vote, so votes from people who left stop counting.
+        listeners: Vec<UserId>,
+        threshold: u8,
+        reply: oneshot::Sender<String>,
+    },
+    SkipTo {
+        position: usize,
+        is_dj: bool,
+        reply: oneshot::Sender<String>,
+    },
+    Remove {
+        position: usize,
+        user_id: UserId,
+        /// DJs may remove anyone's song, everyone else only their own.
+        is_dj: bool,
+        reply: oneshot::Sender<String>,
+    },
+    Move {
+        from: usize,
+        to: usize,
+        reply: oneshot::Sender<String>,
+    },
+    Clear(oneshot::Sender<String>),
+    Shuffle(oneshot::Sender<String>),
+    Undo {
+        user_id: UserId,
+        reply: oneshot::Sender<String>,
+    },
+    Pause(oneshot::Sender<String>),
+    Resume(oneshot::Sender<String>),
+    Seek {
+        target: SeekTarget,
+        reply: oneshot::Sender<String>,
+    },
+    SetVolume {
+        volume: u16,
+        reply: oneshot::Sender<String>,
+    },
+    Stop,
+    Queue(oneshot::Sender<QueueSnapshot>),
+    ListenersChanged {
+        listeners: usize,
+    },
+    VoiceChannelChanged {
+        channel: Option<ChannelId>,
+    },
+    ConnectionLost {
+        reason: String,
+    },
+    Disconnected,
+    TrackEnded {
+        track: TrackHandle,
+        error: Option<String>,
+    },
+    Prefetch {
+        track: TrackHandle,
+    },
+    Crossfade {
+        track: TrackHandle,
+        remaining: Duration,
+    },
+    PrefetchReady {
+        node: Box<Node>,
+        filter: Option<String>,
+        input: Option<Input>,
+    },
+    SetFilter {
+        filter: Option<AudioFilter>,
+        reply: oneshot::Sender<String>,
+    },
+    SetNormalize {
+        normalize: bool,
+        reply: oneshot::Sender<String>,
+    },
+    SetCrossfade {
+        seconds: u64,
+        reply: oneshot::Sender<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    Track,
+    Queue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatCommand {
+    Mode(RepeatMode),
+    Times(usize),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QueueSnapshot {
+    pub current_song: Option<Node>,
+    pub position: Duration,
+    pub queue: VecDeque<Node>,
+}
+
+#[derive(Clone)]
+pub struct PlayerHandle {
+    commands: mpsc::UnboundedSender<PlayerCommand>,
+}
+
+impl PlayerHandle {
+    pub fn spawn(
+        ctx: Context,
+        manager: Arc<Songbird>,
+        settings: Arc<SettingsStore>,
+        cache: Arc<AudioCache>,
+        guild_id: GuildId,
+    ) -> Self {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let player = Player {
+            ctx,
+            manager,
+            settings,
+            cache,
+            guild_id,
+            commands: commands.clone(),
+            text_channel: None,
+            queue: VecDeque::new(),
+            current: None,
+            repeat: RepeatMode::Off,
+            skipping: false,
+            prefetch_lead: prefetch_lead(),
+            prefetching: false,
+            prefetch_due: false,
+            prefetched: None,
+            filter: None,
+            normalize: false,
+            crossfade: Duration::ZERO,
+            fading: None,
+            skip_votes: HashSet::new(),
+            auto_leave: AutoLeave::from_env(),
+            voice_channel: None,
+        };
+
+        tokio::spawn(player.run(receiver));
+        PlayerHandle { commands }
+    }
+
+    pub fn send(&self, command: PlayerCommand) {
+        if self.commands.send(command).is_err() {
+            println!("Player task is no longer running");
+        }
+    }
+
+    pub async fn queue(&self) -> QueueSnapshot {
+        self.request(PlayerCommand::Queue).await
+    }
+
+    pub async fn request<T: Default>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> PlayerCommand,
+    ) -> T {
+        let (reply, response) = oneshot::channel();
+        self.send(command(reply));
+        response.await.unwrap_or_default()
+    }
+}
+
+struct CurrentTrack {
+    node: Node,
+    // What the tracker follows too, so both agree on the song position.
+    playback: TrackedPlayback,
+    // Dropping this sender tells the tracker to remove its message.
+    tracker: watch::Sender<TrackedPlayback>,
+}
+
+struct Player {
+    ctx: Context,
+    manager: Arc<Songbird>,
+    settings: Arc<SettingsStore>,
+    cache: Arc<AudioCache>,
+    guild_id: GuildId,
+    commands: mpsc::UnboundedSender<PlayerCommand>,
+    text_channel: Option<ChannelId>,
+    queue: VecDeque<Node>,
+    current: Option<CurrentTrack>,
+    repeat: RepeatMode,
+    // Set by `!skip` so the ended track isn't replayed by a repeat mode.
+    skipping: bool,
+    prefetch_lead: Duration,
+    prefetching: bool,
+    prefetched: Option<Prefetched>,
+    // The current song is close enough to its end that the next one should be ready,
+    // so a queue change restarts the prefetch for whatever is next now.
+    prefetch_due: bool,
+    filter: Option<AudioFilter>,
+    normalize: bool,
+    crossfade: Duration,
+    fading: Option<TrackHandle>,
+    skip_votes: HashSet<UserId>,
+    auto_leave: AutoLeave,
+    voice_channel: Option<ChannelId>,
+}
+
+struct Prefetched {
+    node: Node,
+    filter: Option<String>,
+    // `Input` isn't `Sync`, and the player holds `&self` across awaits. `None`
+    // when the prefetch failed, so it isn't retried until the next song changes.
+    input: Mutex<Option<Input>>,
+}
+
+fn same_entry(a: &Node, b: &Node) -> bool {
+    a.url == b.url && a.requested_at == b.requested_at && a.start == b.start
+}
+
+// `PREFETCH_SECONDS=0` turns prefetching off.
+fn prefetch_lead() -> Duration {
+    env::var("PREFETCH_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(DEFAULT_PREFETCH_SECONDS))
+}
+
+impl Player {
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<PlayerCommand>) {
+        let settings = self.settings.get(self.guild_id).await;
+        self.normalize = settings.normalize;
+        self.crossfade = settings.crossfade();
+        let mut auto_leave_checks = tokio::time::interval(AUTO_LEAVE_CHECK_INTERVAL);
+        auto_leave_checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let command = tokio::select! {
+                command = receiver.recv() => match command {
+                    Some(command) => command,
+                    None => return,
+                },
+                // Players outside voice have nothing to leave, so they sleep until a command.
+                _ = auto_leave_checks.tick(), if self.voice_channel.is_some() => {
+                    self.check_auto_leave().await;
+                    self.refresh_prefetch();
+                    continue;
+                }
+            };
+
+            match command {
+                PlayerCommand::Enqueue {
+                    nodes,
+                    voice_channel,
+                    text_channel,
+                    play_next,
+                } => {
+                    self.enqueue(nodes, voice_channel, text_channel, play_next)
+                        .await
+                }
+                PlayerCommand::Repeat { repeat, reply } => {
+                    let _ = reply.send(self.set_repeat(repeat));
+                }
+                PlayerCommand::Skip => self.skip(),
+                PlayerCommand::VoteSkip {
+                    user_id,
+                    listeners,
+                    threshold,
+                    reply,
+                } => {
+                    let _ = reply.send(self.vote_skip(user_id, listeners, threshold));
+                }
+                PlayerCommand::SkipTo {
+                    position,
+                    is_dj,
+                    reply,
+                } => {
+                    let _ = reply.send(self.skip_to(position, is_dj).await);
+                }
+                PlayerCommand::Remove {
+                    position,
+                    user_id,
+                    is_dj,
+                    reply,
+                } => {
+                    let _ = reply.send(self.remove(position, user_id, is_dj));
+                }
+                PlayerCommand::Move { from, to, reply } => {
+                    let _ = reply.send(self.move_entry(from, to));
+                }
+                PlayerCommand::Clear(reply) => {
+                    let _ = reply.send(self.clear());
+                }
+                PlayerCommand::Shuffle(reply) => {
+                    let _ = reply.send(self.shuffle());
+                }
+                PlayerCommand::Undo { user_id, reply } => {
+                    let _ = reply.send(self.undo(user_id));
+                }
+                PlayerCommand::Pause(reply) => {
+                    let _ = reply.send(self.pause());
+                }
+                PlayerCommand::Resume(reply) => {
+                    let _ = reply.send(self.resume());
+                }
+                PlayerCommand::Seek { target, reply } => {
+                    let _ = reply.send(self.seek(target).await);
+                }
+                PlayerCommand::SetVolume { volume, reply } => {
+                    let _ = reply.send(self.set_volume(volume).await);
+                }
+                PlayerCommand::Stop => self.stop().await,
+                PlayerCommand::ListenersChanged { listeners } => {
+                    self.auto_leave.listeners_changed(listeners, Instant::now());
+                }
+                PlayerCommand::VoiceChannelChanged { channel } => {
+                    self.voice_channel_changed(channel).await;
+                }
+                PlayerCommand::ConnectionLost { reason } => self.reconnect(reason).await,
+                PlayerCommand::Disconnected => self.disconnected().await,
+                PlayerCommand::Queue(reply) => {
+                    let _ = reply.send(QueueSnapshot {
+                        current_song: self.current.as_ref().map(|current| current.node.clone()),
+                        position: self.position().await,
+                        queue: self.queue.clone(),
+                    });
+                }
+                PlayerCommand::TrackEnded { track, error } => self.track_ended(track, error).await,
+                PlayerCommand::Prefetch { track } => self.prefetch(track),
+                PlayerCommand::Crossfade { track, remaining } => {
+                    self.crossfade(track, remaining).await
+                }
+                PlayerCommand::PrefetchReady {
+                    node,
+                    filter,
+                    input,
+                } => {
+                    self.prefetching = false;
+                    self.prefetched = Some(Prefetched {
+                        node: *node,
+                        filter,
+                        input: Mutex::new(input),
+                    });
+                }
+                PlayerCommand::SetFilter { filter, reply } => {
+                    let _ = reply.send(self.set_filter(filter).await);
+                }
+                PlayerCommand::SetNormalize { normalize, reply } => {
+                    let _ = reply.send(self.set_normalize(normalize).await);
+                }
+                PlayerCommand::SetCrossfade { seconds, reply } => {
+                    let _ = reply.send(self.set_crossfade(seconds).await);
+                }
+            }
+
+            self.refresh_prefetch();
+        }
+    }
+
+    fn prefetch(&mut self, track: TrackHandle) {
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|current| current.playback.handle.uuid() == track.uuid())
+        {
+            self.prefetch_due = true;
+            self.start_prefetch();
+        }
+    }
+
+    fn start_prefetch(&mut self) {
+        let Some(current) = self.current.as_ref() else {
+            return;
+        };
+        if self.prefetching || self.prefetched.is_some() {
+            return;
+        }
+        // A song that's about to repeat doesn't need the next one yet.
+        if current.node.repeats > 0 || self.repeat == RepeatMode::Track {
+            return;
+        }
+        let Some(next) = self.queue.front().cloned() else {
+            return;
+        };
+
+        self.prefetching = true;
+        let commands = self.commands.clone();
+        let cache = self.cache.clone();
+        let filter = self.filter_chain();
+        tokio::spawn(async move {
+            let input = match prefetch_source(&cache, &next, filter.as_deref()).await {
+                Ok(input) => Some(input),
+                Err(why) => {
+                    println!("Error prefetching {}: {:?}", next.url, why);
+                    None
+                }
+            };
+            let _ = commands.send(PlayerCommand::PrefetchReady {
+                node: Box::new(next),
+                filter,
+                input,
+            });
+        });
+    }
+
+    // Dropping a stale prefetch also kills its yt-dlp and ffmpeg processes.
+    fn refresh_prefetch(&mut self) {
+        let filter = self.filter_chain();
+        let stale = self.prefetched.as_ref().is_some_and(|prefetched| {
+            prefetched.filter != filter
+                || !self
+                    .queue
+                    .front()
+                    .is_some_and(|next| same_entry(next, &prefetched.node))
+        });
+        if stale {
+            self.prefetched = None;
+        }
+        if self.prefetch_due {
+            self.start_prefetch();
+        }
+    }
+
+    fn take_prefetched(&mut self, node: &Node) -> Option<Input> {
+        self.prefetched
+            .take()
+            .filter(|prefetched| same_entry(&prefetched.node, node))
+            .and_then(|prefetched| prefetched.input.into_inner().ok().flatten())
+    }
+
+    fn watch_for_end(&self, node: &Node, tracker: &watch::Sender<TrackedPlayback>) {
+        if !self.prefetch_lead.is_zero() {
+            let playback = tracker.subscribe();
+            let lead = self.prefetch_lead + self.crossfade;
+            let commands = self.commands.clone();
+            let length = node.duration;
+            tokio::spawn(async move {
+                if let Some((track, _)) = wait_for_remaining(playback, length, lead).await {
+                    let _ = commands.send(PlayerCommand::Prefetch { track });
+                }
+            });
+        }
+
+        if !self.crossfade.is_zero() {
+            let playback = tracker.subscribe();
+            // Short songs still get most of their playing time at full volume.
+            let lead = self.crossfade.min(node.remaining() / 2);
+            let commands = self.commands.clone();
+            let length = node.duration;
+            tokio::spawn(async move {
+                if let Some((track, remaining)) = wait_for_remaining(playback, length, lead).await {
+                    let _ = commands.send(PlayerCommand::Crossfade { track, remaining });
+                }
+            });
+        }
+    }
+
+    async fn crossfade(&mut self, track: TrackHandle, remaining: Duration) {
+        let Some(current) = self.current.as_ref() else {
+            return;
+        };
+        let should_fade = current.playback.handle.uuid() == track.uuid()
+            && !self.skipping
+            && current.node.repeats == 0
+            && self.repeat != RepeatMode::Track
+            && !self.queue.is_empty()
+            && current
+                .playback
+                .handle
+                .get_info()
+                .await
+                .is_ok_and(|info| matches!(info.playing, PlayMode::Play));
+        if !should_fade {
+            return;
+        }
+
+        let Some(outgoing) = self.current.take() else {
+            return;
+        };
+        let fade = remaining.max(Duration::from_millis(100));
+        let mut started = false;
+        while let Some(node) = self.queue.pop_front() {
+            if self.play(node, Some(fade)).await {
+                started = true;
+                break;
+            }
+        }
+        if !started {
+            self.current = Some(outgoing);
+            return;
+        }
+
+        let CurrentTrack {
+            mut node, playback, ..
+        } = outgoing;
+        let handle = playback.handle;
+        if self.repeat == RepeatMode::Queue {
+            node.repeats = 0;
+            self.queue.push_back(node);
+        }
+
+        self.stop_fading();
+        let volume = self.settings.get(self.guild_id).await.track_volume();
+        self.fading = Some(handle.clone());
+        tokio::spawn(async move {
+            fade_volume(handle.clone(), volume, 0.0, fade).await;
+            let _ = handle.stop();
+        });
+    }
+
+    fn stop_fading(&mut self) {
+        if let Some(fading) = self.fading.take() {
+            let _ = fading.stop();
+        }
+    }
+
+    async fn set_crossfade(&mut self, seconds: u64) -> String {
+        let settings = match self
+            .settings
+            .update(self.guild_id, |settings| {
+                settings.crossfade_seconds = seconds
+            })
+            .await
+        {
+            Ok(settings) => settings,
+            Err(why) => {
+                println!("Error saving guild settings: {:?}", why);
+                return format!("Couldn't save the crossfade: {}", why);
+            }
+        };
+
+        // Takes effect from the next song, whose end watchers use the new length.
+        self.crossfade = settings.crossfade();
+        if seconds == 0 {
+            "Crossfade off".to_owned()
+        } else {
+            format!("Crossfading {} seconds between songs", seconds)
+        }
+    }
+
+    async fn enqueue(
+        &mut self,
+        nodes: Vec<Node>,
+        voice_channel: ChannelId,
+        text_channel: ChannelId,
+        play_next: bool,
+    ) {
+        self.text_channel = Some(text_channel);
+
+        if play_next {
+            // Keep a batch in its original order ahead of everything else.
+            for node in nodes.into_iter().rev() {
+                self.queue.push_front(node);
+            }
+        } else {
+            self.queue.extend(nodes);
+        }
+        if self.current.is_some() {
+            return;
+        }
+
+        if !self.join(voice_channel).await {
+            self.queue.clear();
+            return;
+        }
+
+        self.play_next().await;
+    }
+
+    fn set_repeat(&mut self, repeat: RepeatCommand) -> String {
+        match repeat {
+            RepeatCommand::Mode(mode) => {
+                self.repeat = mode;
+                match mode {
+                    RepeatMode::Off => "Repeat is off".to_owned(),
+                    RepeatMode::Track => "Repeating the current song".to_owned(),
+                    RepeatMode::Queue => "Repeating the whole queue".to_owned(),
+                }
+            }
+            RepeatCommand::Times(count) => {
+                let Some(current) = self.current.as_mut() else {
+                    return "Nothing playing".to_owned();
+                };
+                current.node.repeats = count;
+                format!("Playing this song {} more times", count)
+            }
+        }
+    }
+
+    fn skip(&mut self) {
+        self.stop_fading();
+        if let Some(current) = self.current.as_ref() {
+            self.skipping = true;
+            let _ = current.playback.handle.stop();
+        }
+    }
+
+    fn vote_skip(&mut self, user_id: UserId, listeners: Vec<UserId>, threshold: u8) -> String {
+        let Some(current) = self.current.as_ref() else {
+            return "Nothing playing".to_owned();
+        };
+        if current.node.requested_by == Some(user_id) {
+            self.skip();
+            return String::new();
+        }
+        if !listeners.contains(&user_id) {
+            return "Join the voice channel to vote".to_owned();
+        }
+
+        self.skip_votes.insert(user_id);
+        let votes = self
+            .skip_votes
+            .iter()
+            .filter(|voter| listeners.contains(voter))
+            .count();
+        let needed = votes_needed(listeners.len(), threshold);
+        if votes >= needed {
+            self.skip();
+            format!("Vote passed ({}/{}), skipping", votes, needed)
+        } else {
+            format!("Vote to skip: {}/{}", votes, needed)
+        }
+    }
+
+    async fn skip_to(&mut self, position: usize, is_dj: bool) -> String {
+        if position == 0 || position > self.queue.len() {
+            return format!("There's no song #{} in the queue", position);
+        }
+        if !may_skip_to(position, self.current.is_some(), is_dj) {
+            return "🚫 Only DJs can skip the current song or drop queued songs with `!skipto`; use `!skip` to vote.".to_owned();
+        }
+        skip_queue_to(&mut self.queue, position);
+
+        if self.current.is_some() {
+            self.skip();
+        } else {
+            self.play_next().await;
+        }
+        format!("Skipping to #{}", position)
+    }
+
+    fn remove(&mut self, position: usize, user_id: UserId, is_dj: bool) -> String {
+        let Some(node) = position
+            .checked_sub(1)
+            .and_then(|index| self.queue.get(index))
+        else {
+            return format!("There's no song #{} in the queue", position);
+        };
+        if !is_dj && node.requested_by != Some(user_id) {
+            return "🚫 Only DJs can remove other people's songs.".to_owned();
+        }
+
+        match remove_queue_entry(&mut self.queue, position) {
+            Some(node) => format!("Removed #{}: {}", position, node.title()),
+            None => format!("There's no song #{} in the queue", position),
+        }
+    }
+
+    fn move_entry(&mut self, from: usize, to: usize) -> String {
+        if move_queue_entry(&mut self.queue, from, to) {
+            format!("Moved #{} to #{}", from, to)
+        } else {
+            format!(
+                "Can't move #{} to #{}, the queue has {} songs",
+                from,
+                to,
+                self.queue.len()
+            )
+        }
+    }
+
+    fn clear(&mut self) -> String {
+        let cleared = self.queue.len();
+        self.queue.clear();
+        format!("Cleared {} queued songs", cleared)
+    }
+
+    fn shuffle(&mut self) -> String {
+        if self.queue.len() < 2 {
+            return "Not enough queued songs to shuffle".to_owned();
+        }
+
+        shuffle_queue(&mut self.queue);
+        format!("Shuffled {} queued songs", self.queue.len())
+    }
+
+    fn undo(&mut self, user_id: UserId) -> String {
+        match undo_last_request(&mut self.queue, user_id) {
+            Some(node) => format!("Removed your last request: {}", node.title()),
+            None => "You don't have any songs in the queue".to_owned(),
+        }
+    }
+
+    fn pause(&mut self) -> String {
+        // Pausing mid-crossfade just lets the outgoing song go.
+        self.stop_fading();
+        let Some(current) = self.current.as_ref() else {
+            return "Nothing playing".to_owned();
+        };
+
+        if let Err(why) = current.playback.handle.pause() {
+            println!("Error pausing track: {:?}", why);
+        }
+        String::new()
+    }
+
+    fn resume(&self) -> String {
+        let Some(current) = self.current.as_ref() else {
+            return "Nothing playing".to_owned();
+        };
+
+        if let Err(why) = current.playback.handle.play() {
+            println!("Error resuming track: {:?}", why);
+        }
+        String::new()
+    }
+
+    async fn seek(&mut self, target: SeekTarget) -> String {
+        let Some(current) = self.current.as_ref() else {
+            return "Nothing playing".to_owned();
+        };
+
+        if current.node.is_live() {
+            return "Can't seek in a live stream".to_owned();
+        }
+
+        let info = match current.playback.handle.get_info().await {
+            Ok(info) => info,
+            Err(why) => {
+                println!("Error reading track position: {:?}", why);
+                return format!("Couldn't seek: {}", why);
+            }
+        };
+        let position = target.resolve(
+            current.playback.position(info.position),
+            current.node.known_length(),
+        );
+
+        // Piped yt-dlp audio can't seek in place, so start a fresh pipeline at the target.
+        match self.restart_at(position).await {
+            Ok(()) => String::new(),
+            Err(why) => {
+                println!("Error seeking YouTube audio: {:?}", why);
+                format!("Couldn't seek: {}", why)
+            }
+        }
+    }
+
+    async fn set_filter(&mut self, filter: Option<AudioFilter>) -> String {
+        if self.filter == filter {
+            return match filter {
+                Some(filter) => format!("The {} filter is already on", filter),
+                None => "No filter is on".to_owned(),
+            };
+        }
+
+        let reply = match &filter {
+            Some(filter) => format!("Filter set to {}", filter),
+            None => "Filters off".to_owned(),
+        };
+        self.filter = filter;
+        self.restart_with_new_chain(reply).await
+    }
+
+    async fn set_normalize(&mut self, normalize: bool) -> String {
+        if let Err(why) = self
+            .settings
+            .update(self.guild_id, |settings| settings.normalize = normalize)
+            .await
+        {
+            println!("Error saving guild settings: {:?}", why);
+            return format!("Couldn't save the normalization setting: {}", why);
+        }
+
+        let reply = if normalize {
+            "Loudness normalization on"
+        } else {
+            "Loudness normalization off"
+        };
+        if self.normalize == normalize {
+            return reply.to_owned();
+        }
+
+        self.normalize = normalize;
+        self.restart_with_new_chain(reply.to_owned()).await
+    }
+
+    async fn restart_with_new_chain(&mut self, reply: String) -> String {
+        if self.current.is_none() {
+            return reply;
+        }
+
+        let position = self.position().await;
+        match self.restart_at(position).await {
+            Ok(()) => reply,
+            Err(why) => {
+                println!("Error restarting audio with new filters: {:?}", why);
+                format!("{}, but couldn't restart the song: {}", reply, why)
+            }
+        }
+    }
+
+    async fn restart_at(
+        &mut self,
+        position: Duration,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(current) = self.current.as_ref() else {
+            return Ok(());
+        };
+        let paused = current
+            .playback
+            .handle
+            .get_info()
+            .await
+            .is_ok_and(|info| matches!(info.playing, PlayMode::Pause));
+        let node = current.node.clone();
+        // Streams can only rejoin at the live edge; the offset keeps the elapsed time going.
+        let start = if node.is_live() {
+            Duration::ZERO
+        } else {
+            position
+        };
+
+        let handle = self.start_track(&node, start, None, false).await?;
+        if paused {
+            let _ = handle.pause();
+        }
+
+        let speed = self.speed();
+        if let Some(current) = self.current.as_mut() {
+            // Tracks no longer replace each other, so the old pipeline is stopped by hand.
+            let _ = current.playback.handle.stop();
+            current.playback = TrackedPlayback {
+                handle,
+                offset: position,
+                speed,
+            };
+            current.tracker.send_replace(current.playback.clone());
+        }
+        Ok(())
+    }
+
+    async fn position(&self) -> Duration {
+        let Some(current) = self.current.as_ref() else {
+            return Duration::ZERO;
+        };
+
+        match current.playback.handle.get_info().await {
+            Ok(info) => current.playback.position(info.position),
+            Err(_) => current.playback.offset,
+        }
+    }
+
+    fn filter_chain(&self) -> Option<String> {
+        audio_filter_chain(self.filter.as_ref(), self.normalize)
+    }
+
+    fn speed(&self) -> f64 {
+        self.filter.as_ref().map_or(1.0, AudioFilter::speed)
+    }
+
+    async fn set_volume(&self, volume: u16) -> String {
+        let settings = match self
+            .settings
+            .update(self.guild_id, |settings| settings.volume = volume)
+            .await
+        {
+            Ok(settings) => settings,
+            Err(why) => {
+                println!("Error saving guild settings: {:?}", why);
+                return format!("Couldn't save the volume: {}", why);
+            }
+        };
+
+        if let Some(current) = self.current.as_ref() {
+            if let Err(why) = current.playback.handle.set_volume(settings.track_volume()) {
+                println!("Error setting track volume: {:?}", why);
+            }
+        }
+
+        format!("Volume set to {}%", settings.volume)
+    }
+
+    async fn check_auto_leave(&mut self) {
+        if self.manager.get(self.guild_id).is_none() {
+            self.auto_leave.reset();
+            return;
+        }
+
+        let playing = match self.current.as_ref() {
+            Some(current) => current
+                .playback
+                .handle
+                .get_info()
+                .await
+                .is_ok_and(|info| matches!(info.playing, PlayMode::Play)),
+            None => false,
+        };
+        let now = Instant::now();
+        self.auto_leave.playing_changed(playing, now);
+
+        let Some(reason) = self.auto_leave.due(now) else {
+            return;
+        };
+        if self.settings.get(self.guild_id).await.stay_in_voice {
+            return;
+        }
+
+        self.say(&reason).await;
+        self.stop().await;
+    }
+
+    async fn voice_channel_changed(&mut self, channel: Option<ChannelId>) {
+        if let Some(channel) = channel {
+            // Songbird reconnects to the new channel by itself and the song carries on.
+            if self.voice_channel.is_some_and(|current| current != channel) {
+                self.voice_channel = Some(channel);
+                self.auto_leave.reset();
+            }
+            return;
+        }
+
+        let Some(handler_lock) = self.manager.get(self.guild_id) else {
+            // We left on our own and already cleaned up.
+            return;
+        };
+        if handler_lock.lock().await.current_channel().is_some() {
+            // A stale update from before a rejoin.
+            return;
+        }
+
+        self.clear_after_disconnect().await;
+    }
+
+    async fn disconnected(&mut self) {
+        if self.manager.get(self.guild_id).is_none() {
+            return;
+        }
+        match self.bot_voice_channel() {
+            // Moved rather than kicked; the voice state update follows the move.
+            Some(channel) if self.voice_channel != Some(channel) => {
+                self.voice_channel_changed(Some(channel)).await;
+            }
+            _ => self.clear_after_disconnect().await,
+        }
+    }
+
+    async fn clear_after_disconnect(&mut self) {
+        if self.current.is_some() || !self.queue.is_empty() {
+            self.say("Disconnected from voice, so I cleared the queue")
+                .await;
+        }
+        self.stop().await;
+    }
+
+    async fn reconnect(&mut self, reason: String) {
+        if self.voice_channel.is_none() {
+            return;
+        }
+        println!("Voice connection lost: {}", reason);
+        // A kick or move may land after the drop, so rejoin wherever the bot is now.
+        let Some(channel) = self.bot_voice_channel() else {
+            self.clear_after_disconnect().await;
+            return;
+        };
+
+        let position = self.position().await;
+        self.stop_fading();
+        if !self.join(channel).await {
+            self.stop().await;
+            return;
+        }
+
+        if let Err(why) = self.restart_at(position).await {
+            println!("Error resuming audio after reconnecting: {:?}", why);
+            self.say(&format!("Couldn't resume the song: {}", why))
+                .await;
+            if let Some(current) = self.current.take() {
+                let _ = current.playback.handle.stop();
+            }
+            self.play_next().await;
+        }
+    }
+
+    fn bot_voice_channel(&self) -> Option<ChannelId> {
+        let bot_id = self.ctx.cache.current_user().id;
+        let guild = self.ctx.cache.guild(self.guild_id)?;
+        guild.voice_states.get(&bot_id)?.channel_id
+    }
+
+    async fn stop(&mut self) {
+        self.queue.clear();
+        self.repeat = RepeatMode::Off;
+        self.filter = None;
+        self.prefetched = None;
+        self.prefetch_due = false;
+        self.skip_votes.clear();
+        self.auto_leave.reset();
+        self.voice_channel = None;
+        self.stop_fading();
+        if let Some(current) = self.current.take() {
+            let _ = current.playback.handle.stop();
+        }
+
+        if self.manager.get(self.guild_id).is_some() {
+            if let Err(why) = self.manager.remove(self.guild_id).await {
+                println!("Error leaving voice channel: {:?}", why);
+            }
+        }
+    }
+
+    async fn track_ended(&mut self, track: TrackHandle, error: Option<String>) {
+        if self
+            .fading
+            .as_ref()
+            .is_some_and(|fading| fading.uuid() == track.uuid())
+        {
+            self.fading = None;
+            return;
+        }
+
+        let is_current = self
+            .current
+            .as_ref()
+            .is_some_and(|current| current.playback.handle.uuid() == track.uuid());
+        if !is_current {
+            return;
+        }
+
+        let Some(finished) = self.current.take() else {
+            return;
+        };
+        let skipped = std::mem::take(&mut self.skipping);
+        let mut node = finished.node;
+
+        if let Some(why) = error {
+            println!("Error playing YouTube audio: {}", why);
+            self.say(&format!("Couldn't play that audio: {}", why))
+                .await;
+            self.play_next().await;
+            return;
+        }
+
+        if !skipped {
+            if node.repeats > 0 {
+                node.repeats -= 1;
+                self.play_or_advance(node).await;
+                return;
+            }
+
+            if self.repeat == RepeatMode::Track {
+                self.play_or_advance(node).await;
+                return;
+            }
+        }
+
+        if self.repeat == RepeatMode::Queue {
+            node.repeats = 0;
+            self.queue.push_back(node);
+        }
+
+        self.play_next().await;
+    }
+
+    async fn play_or_advance(&mut self, node: Node) {
+        if !self.play(node, None).await {
+            self.play_next().await;
+        }
+    }
+
+    async fn play_next(&mut self) {
+        while let Some(node) = self.queue.pop_front() {
+            if self.play(node, None).await {
+                return;
+            }
+        }
+    }
+
+    async fn play(&mut self, node: Node, fade_in: Option<Duration>) -> bool {
+        let prefetched = self.take_prefetched(&node);
+        let handle = match self
+            .start_track(&node, node.start, prefetched, fade_in.is_some())
+            .await
+        {
+            Ok(handle) => handle,
+            Err(why) => {
+                println!("Error playing YouTube audio: {:?}", why);
+                self.say(&format!("Couldn't play that audio: {}", why))
+                    .await;
+                return false;
+            }
+        };
+
+        let playback = TrackedPlayback {
+            handle: handle.clone(),
+            offset: node.start,
+            speed: self.speed(),
+        };
+        let (tracker_updates, tracked_playback) = watch::channel(playback.clone());
+        if let Some(text_channel) = self.text_channel {
+            let ctx = self.ctx.clone();
+            let tracked = node.clone();
+            tokio::spawn(async move {
+                tracker(ctx, text_channel, tracked, tracked_playback).await;
+            });
+        }
+        if let Some(fade_in) = fade_in {
+            let volume = self.settings.get(self.guild_id).await.track_volume();
+            tokio::spawn(fade_volume(handle.clone(), 0.0, volume, fade_in));
+        }
+        if node.known_length().is_some() {
+            self.watch_for_end(&node, &tracker_updates);
+        }
+
+        self.skipping = false;
+        self.skip_votes.clear();
+        self.prefetch_due = false;
+        self.current = Some(CurrentTrack {
+            node,
+            playback,
+            tracker: tracker_updates,
+        });
+        true
+    }
+
+    async fn start_track(
+        &self,
+        node: &Node,
+        start: Duration,
+        prefetched: Option<Input>,
+        silent: bool,
+    ) -> Result<TrackHandle, Box<dyn std::error::Error + Send + Sync>> {
+        let handler_lock = self.manager.get(self.guild_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "not connected to a voice channel for playback",
+            )
+        })?;
+
+        let source = match prefetched {
+            Some(source) => source,
+            None => create_node_input(&self.cache, node, start, self.filter_chain().as_deref())?,
+        };
+        let volume = if silent {
+            0.0
+        } else {
+            self.settings.get(self.guild_id).await.track_volume()
+        };
+        let handle = {
+            let mut handler = handler_lock.lock().await;
+            play_source(&mut handler, source, volume, self.commands.clone())?
+        };
+
+        Ok(handle)
+    }
+
+    async fn join(&mut self, voice_channel: ChannelId) -> bool {
+        match self.manager.join(self.guild_id, voice_channel).await {
+            Ok(handler_lock) => {
+                watch_connection(&mut *handler_lock.lock().await, self.commands.clone());
+                self.voice_channel = Some(voice_channel);
+                // Rejoining an empty channel sends no voice update, so count the
+                // people there now rather than waiting for one.
+                self.auto_leave.reset();
+                let listeners = channel_listeners(&self.ctx, self.guild_id, voice_channel);
+                self.auto_leave
+                    .listeners_changed(listeners.len(), Instant::now());
+                true
+            }
+            Err(why) => {
+                println!("Failed to join voice: {:?}", why);
+                self.say(&format!("Couldn't join your voice channel: {}", why))
+                    .await;
+                false
+            }
+        }
+    }
+
+    async fn say(&self, content: &str) {
+        let Some(text_channel) = self.text_channel else {
+            return;
+        };
+
+        if let Err(why) = text_channel.say(&self.ctx.http, content).await {
+            println!("Error sending player message: {:?}", why);
+        }
+    }
+}
@@ -1,119 +1,123 @@
 use crate::resources::*;
-use crate::utils::*;
 use serenity::{
-    builder::{CreateEmbed, EditMessage},
-    model::channel::Message,
+    builder::{CreateEmbed, CreateEmbedFooter, EditMessage},
+    model::prelude::ChannelId,
     prelude::Context,
 };
-use std::{
-    cmp::max,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
+use songbird::tracks::{PlayMode, TrackHandle};
+use std::{cmp::max, time::Duration};
+use tokio::{
+    sync::watch,
+    time::{interval, MissedTickBehavior},
 };
-use tokio::time::{sleep, Instant};
+
+/// The track the tracker should follow. Seeking restarts the audio pipeline, so
+/// the handle changes and `offset` records where the new stream starts.
+#[derive(Clone)]
+pub struct TrackedPlayback {
+    pub handle: TrackHandle,
+    pub offset: Duration,
+    pub speed: f64,
+}
+
+impl TrackedPlayback {
+    pub fn position(&self, played: Duration) -> Duration {
+        self.offset + played.mul_f64(self.speed)
+    }
+}
 
 pub async fn tracker(
     ctx: Context,
-    skip: Arc<AtomicBool>,
-    tracking_mutex: Arc<tokio::sync::Mutex<bool>>,
-    msg: Message,
+    channel_id: ChannelId,
     node: Node,
+    playback: watch::Receiver<TrackedPlayback>,
 ) {
-    {
-        let mut tracking = tracking_mutex.lock().await;
-        *tracking = true;
-    }
-
-    let result = run_tracker(ctx, skip, msg, node).await;
-
-    {
-        let mut tracking = tracking_mutex.lock().await;
-        *tracking = false;
-    }
-
-    if let Err(why) = result {
+    if let Err(why) = run_tracker(ctx, channel_id, node, playback).await {
         println!("Tracker error: {:?}", why);
     }
 }
 
 async fn run_tracker(
     ctx: Context,
-    skip: Arc<AtomicBool>,
-    msg: Message,
+    channel_id: ChannelId,
     node: Node,
+    mut playback: watch::Receiver<TrackedPlayback>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let url = node.url;
-    let duration = node.duration;
-    let count = max(1, duration.as_secs() / NUMBER_OF_PROGRESS_BARS);
+    let live = node.is_live();
+    let length = node.known_length();
+    let period = match length {
+        Some(length) => Duration::from_secs(max(1, length.as_secs() / NUMBER_OF_PROGRESS_BARS)),
+        None => LIVE_TRACKER_INTERVAL,
+    };
 
     let mut first_update = true;
-    let mut current_time = 0;
-    let start_time = Instant::now();
-    let mut next_tick = start_time + Duration::from_secs(count);
-
-    let clean_video_title = match get_video_title(&url).await {
-        Ok(title) => title.replace(['\n', '\r'], " "),
-        Err(why) => {
-            println!("Error getting video title: {:?}", why);
-            url.clone()
-        }
-    };
+    let mut last_rendered = None;
+    let mut ticks = interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let clean_video_title = node.title().replace(['\n', '\r'], " ");
     let new_content = format!("Playing: ```{}```", clean_video_title);
-    let mut created_message = msg.channel_id.say(&ctx.http, new_content).await?;
+    let mut created_message = channel_id.say(&ctx.http, new_content).await?;
 
     let content = format!("```{}```", clean_video_title);
 
-    while current_time < duration.as_secs() {
-        let now = Instant::now();
-        if now >= next_tick {
-            current_time += count;
-            let duration_str = format!(
-                "{}:{:02} / {}:{:02}",
-                current_time / 60,
-                current_time % 60,
-                duration.as_secs() / 60,
-                duration.as_secs() % 60
-            );
-
-            let progress = ((current_time as f64 / duration.as_secs() as f64)
-                * NUMBER_OF_PROGRESS_BARS as f64)
-                .floor() as usize;
-            let progress = progress.min(NUMBER_OF_PROGRESS_BARS as usize);
-            let progress_bar = "█".repeat(progress);
-            let empty_space =
-                "░".repeat((NUMBER_OF_PROGRESS_BARS as usize).saturating_sub(progress));
-
-            let combined_field = format!("{}\n{}{}", duration_str, progress_bar, empty_space);
-
-            let embed = CreateEmbed::new()
-                .title("Now Playing")
-                .description(content.clone())
-                .field("Progress", combined_field, false);
-            let mut edit = EditMessage::new().embed(embed);
-
-            if first_update {
-                edit = edit.content(" ");
-                first_update = false;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            changed = playback.changed() => {
+                if changed.is_err() {
+                    break;
+                }
             }
+        }
 
-            if let Err(why) = created_message.edit(&ctx.http, edit).await {
-                println!("Error editing tracker message: {:?}", why);
-                break;
-            }
+        let tracked = playback.borrow_and_update().clone();
 
-            next_tick += Duration::from_secs(count);
+        // Songbird only advances the position while audio is mixed, so a paused
+        // track keeps reporting the same value and the embed stays put.
+        let Ok(info) = tracked.handle.get_info().await else {
+            continue;
+        };
+        let mut current_time = tracked.position(info.position).as_secs();
+        if let Some(length) = length {
+            current_time = current_time.min(length.as_secs());
+        }
+        let paused = matches!(info.playing, PlayMode::Pause);
+        if last_rendered == Some((current_time, paused)) {
+            continue;
+        }
+        last_rendered = Some((current_time, paused));
+
+        let mut embed = CreateEmbed::new()
+            .title("Now Playing")
+            .description(content.clone())
+            .field(
+                if live { "🔴 LIVE" } else { "Progress" },
+                match length {
+                    Some(length) => progress_field(current_time, length.as_secs()),
+                    None => live_field(current_time),
+                },
+                false,
+            );
+        if node.metadata.webpage_url.starts_with("http") {
+            embed = embed.url(&node.metadata.webpage_url);
         }
+        if let Some(thumbnail) = &node.metadata.thumbnail {
+            embed = embed.thumbnail(thumbnail);
+        }
+        if paused {
+            embed = embed.footer(CreateEmbedFooter::new("Paused"));
+        }
+        let mut edit = EditMessage::new().embed(embed);
 
-        if skip.load(Ordering::SeqCst) {
-            skip.store(false, Ordering::SeqCst);
-            break;
+        if first_update {
+            edit = edit.content(" ");
+            first_update = false;
         }
 
-        if current_time < duration.as_secs() {
-            sleep(Duration::from_millis(100)).await;
+        if let Err(why) = created_message.edit(&ctx.http, edit).await {
+            println!("Error editing tracker message: {:?}", why);
+            break;
         }
     }
 
@@ -123,3 +127,64 @@ async fn run_tracker(
 
     Ok(())
 }
+
+fn live_field(elapsed: u64) -> String {
+    let clock = if elapsed >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            elapsed / 3600,
+            elapsed % 3600 / 60,
+            elapsed % 60
+        )
+    } else {
+        format!("{}:{:02}", elapsed / 60, elapsed % 60)
+    };
+
+    format!("{} elapsed", clock)
+}
+
+fn progress_field(current_time: u64, total_time: u64) -> String {
+    let duration_str = format!(
+        "{}:{:02} / {}:{:02}",
+        current_time / 60,
+        current_time % 60,
+        total_time / 60,
+        total_time % 60
+    );
+
+    let progress = if total_time == 0 {
+        0
+    } else {
+        ((current_time as f64 / total_time as f64) * NUMBER_OF_PROGRESS_BARS as f64).floor()
+            as usize
+    };
+    let progress = progress.min(NUMBER_OF_PROGRESS_BARS as usize);
+    let progress_bar = "█".repeat(progress);
+    let empty_space = "░".repeat((NUMBER_OF_PROGRESS_BARS as usize).saturating_sub(progress));
+
+    format!("{}\n{}{}", duration_str, progress_bar, empty_space)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{live_field, progress_field};
+
+    #[test]
+    fn progress_field_scales_to_track_length() {
+        let field = progress_field(90, 180);
+        assert!(field.starts_with("1:30 / 3:00\n"));
+        assert_eq!(field.matches('█').count(), 24);
+        assert_eq!(field.matches('░').count(), 25);
+    }
+
+    #[test]
+    fn progress_field_handles_zero_length_tracks() {
+        assert!(progress_field(0, 0).ends_with(&"░".repeat(49)));
+    }
+
+    #[test]
+    fn live_field_shows_elapsed_time() {
+        assert_eq!(live_field(75), "1:15 elapsed");
+        assert_eq!(live_field(3725), "1:02:05 elapsed");
+    }
+}
@@ -0,0 +1,176 @@
+use crate::settings::GuildSettings;
+use crate::systems::{is_dj, require_dj, PlayerCommand};
+use crate::Handler;
+use serenity::{
+    model::{
+        channel::Message,
+        prelude::{ChannelId, GuildId, UserId},
+    },
+    prelude::Context,
+};
+
+/// Handles `!skip`. DJs and whoever asked for the current song skip straight
+/// away; everyone else votes once vote-skip is on or a DJ role is set.
+pub async fn skip(app: &Handler, msg: Message, guild_id: GuildId, ctx: &Context) {
+    let settings = app.settings.get(guild_id).await;
+    let player = app.player(ctx, guild_id).await;
+    if can_skip_without_vote(ctx, &msg, &settings) {
+        player.send(PlayerCommand::Skip);
+        return;
+    }
+
+    let user_id = msg.author.id;
+    let listeners = voice_listeners(ctx, guild_id);
+    let threshold = settings.skip_threshold;
+    let reply = player
+        .request(|reply| PlayerCommand::VoteSkip {
+            user_id,
+            listeners,
+            threshold,
+            reply,
+        })
+        .await;
+    if reply.is_empty() {
+        return;
+    }
+
+    if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+        println!("Error sending vote-skip reply: {:?}", why);
+    }
+}
+
+/// Handles `!voteskip [on|off|<n>%]`; only DJs may change it.
+pub async fn vote_skip_setting(
+    app: &Handler,
+    args: &str,
+    msg: Message,
+    guild_id: GuildId,
+    ctx: &Context,
+) {
+    let reply = match parse_vote_skip_args(args) {
+        Ok((vote_skip, threshold)) => {
+            if (vote_skip.is_some() || threshold.is_some())
+                && !require_dj(app, ctx, &msg, guild_id).await
+            {
+                return;
+            }
+            let change = |settings: &mut GuildSettings| {
+                if let Some(vote_skip) = vote_skip {
+                    settings.vote_skip = vote_skip;
+                }
+                if let Some(threshold) = threshold {
+                    settings.skip_threshold = threshold;
+                }
+            };
+            match app.settings.update(guild_id, change).await {
+                Ok(settings) if settings.vote_skip => format!(
+                    "Vote-skip is on, {}% of listeners have to agree",
+                    settings.skip_threshold
+                ),
+                Ok(_) => "Vote-skip is off".to_owned(),
+                Err(why) => {
+                    println!("Error saving guild settings: {:?}", why);
+                    format!("Couldn't save the vote-skip setting: {}", why)
+                }
+            }
+        }
+        Err(message) => message.to_owned(),
+    };
+
+    if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+        println!("Error sending vote-skip reply: {:?}", why);
+    }
+}
+
+/// `(None, None)` just shows the current setting; a threshold also turns vote-skip on.
+fn parse_vote_skip_args(args: &str) -> Result<(Option<bool>, Option<u8>), &'static str> {
+    match args.trim() {
+        "" => Ok((None, None)),
+        "on" => Ok((Some(true), None)),
+        "off" => Ok((Some(false), None)),
+        threshold => threshold
+            .trim_end_matches('%')
+            .parse::<u8>()
+            .ok()
+            .filter(|threshold| (1..=100).contains(threshold))
+            .map(|threshold| (Some(true), Some(threshold)))
+            .ok_or("Expected format: `!voteskip on|off` or `!voteskip <1-100>%`"),
+    }
+}
+
+/// Everyone may skip outright until vote-skip is on or a DJ role is set; after
+/// that only DJs can.
+pub fn can_skip_without_vote(ctx: &Context, msg: &Message, settings: &GuildSettings) -> bool {
+    let open_skip = !settings.vote_skip && settings.dj_role.is_none();
+    open_skip || is_dj(ctx, msg, settings)
+}
+
+pub fn voice_listeners(ctx: &Context, guild_id: GuildId) -> Vec<UserId> {
+    let bot_id = ctx.cache.current_user().id;
+    let channel_id = ctx.cache.guild(guild_id).and_then(|guild| {
+        guild
+            .voice_states
+            .get(&bot_id)
+            .and_then(|voice_state| voice_state.channel_id)
+    });
+
+    match channel_id {
+        Some(channel_id) => channel_listeners(ctx, guild_id, channel_id),
+        None => Vec::new(),
+    }
+}
+
+pub fn channel_listeners(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Vec<UserId> {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return Vec::new();
+    };
+
+    guild
+        .voice_states
+        .values()
+        .filter(|voice_state| voice_state.channel_id == Some(channel_id))
+        .filter(|voice_state| {
+            let is_bot = voice_state
+                .member
+                .as_ref()
+                .map(|member| member.user.bot)
+                .or_else(|| {
+                    guild
+                        .members
+                        .get(&voice_state.user_id)
+                        .map(|member| member.user.bot)
+                })
+                .unwrap_or(false);
+            !is_bot
+        })
+        .map(|voice_state| voice_state.user_id)
+        .collect()
+}
+
+pub fn votes_needed(listeners: usize, threshold: u8) -> usize {
+    (listeners * usize::from(threshold)).div_ceil(100).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_vote_skip_args, votes_needed};
+
+    #[test]
+    fn needs_a_share_of_the_listeners_rounded_up() {
+        assert_eq!(votes_needed(4, 50), 2);
+        assert_eq!(votes_needed(5, 50), 3);
+        assert_eq!(votes_needed(3, 100), 3);
+        assert_eq!(votes_needed(1, 50), 1);
+        assert_eq!(votes_needed(0, 50), 1);
+    }
+
+    #[test]
+    fn parses_vote_skip_arguments() {
+        assert_eq!(parse_vote_skip_args(""), Ok((None, None)));
+        assert_eq!(parse_vote_skip_args("off"), Ok((Some(false), None)));
+        assert_eq!(parse_vote_skip_args(" 60% "), Ok((Some(true), Some(60))));
+        assert!(parse_vote_skip_args("0%").is_err());
+        assert!(parse_vote_skip_args("101").is_err());
+        assert!(parse_vote_skip_args("maybe").is_err());
+    }
+}
@@ -1,23 +1,36 @@
-use serenity::{client::Context, model::prelude::ChannelId};
-use songbird::input::{ChildContainer, Input, RawAdapter};
+use crate::library::LocalFileResolver;
+use crate::resources::FFMPEG_OPTIONS;
+use serde::Deserialize;
+use serenity::{async_trait, client::Context, model::prelude::ChannelId};
+use songbird::input::{
+    codecs::{get_codec_registry, get_probe},
+    ChildContainer, Input, RawAdapter,
+};
 use std::{
-    io,
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
     process::{Command, Stdio},
     time::Duration,
 };
-use symphonia_core::io::ReadOnlySource;
+use symphonia_core::{
+    audio::{SampleBuffer, SignalSpec},
+    codecs::{Decoder, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::{MediaSource, MediaSourceStream, ReadOnlySource},
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::{Hint, ProbeResult},
+    units::{Time, TimeBase},
+};
 use tokio::process::Command as TokioCommand;
 
-pub fn extract_youtube_url(input: &str) -> Result<&str, Box<dyn std::error::Error + Send + Sync>> {
+pub fn extract_source_url(input: &str) -> Result<&str, Box<dyn std::error::Error + Send + Sync>> {
     input
         .split_whitespace()
         .map(trim_url_token)
-        .find(|token| is_valid_youtube_url(token))
-        .ok_or_else(|| youtube_url_error().into())
-}
-
-fn youtube_url_error() -> io::Error {
-    io::Error::new(io::ErrorKind::InvalidInput, "No valid YouTube URL found")
+        .find(|token| resolve_source(token).is_some())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No playable URL found").into())
 }
 
 fn trim_url_token(token: &str) -> &str {
@@ -44,6 +57,10 @@ pub fn is_valid_youtube_url(url: &str) -> bool {
         });
     }
 
+    if let Some(query) = rest.strip_prefix("youtube.com/playlist?") {
+        return query_param(query, "list").is_some_and(valid_playlist_id);
+    }
+
     if let Some(path) = rest.strip_prefix("youtu.be/") {
         return first_path_segment(path)
             .map(valid_youtube_id)
@@ -59,13 +76,87 @@ pub fn is_valid_youtube_url(url: &str) -> bool {
     false
 }
 
+pub fn youtube_playlist_page_id(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let rest = rest.strip_prefix("www.").unwrap_or(rest);
+    let query = rest.strip_prefix("youtube.com/playlist?")?;
+    query_param(query, "list").filter(|id| valid_playlist_id(id))
+}
+
+pub fn youtube_video_playlist_id(url: &str) -> Option<&str> {
+    if !is_valid_youtube_url(url) || youtube_playlist_page_id(url).is_some() {
+        return None;
+    }
+
+    let (_, query) = url.split_once('?')?;
+    query_param(query, "list").filter(|id| valid_playlist_id(id))
+}
+
+pub fn parse_start_offset(url: &str) -> Option<Duration> {
+    let (_, params) = url.split_once(['?', '#'])?;
+    parse_time_offset(query_param(params, "t")?)
+}
+
+/// Parses `90`, `90s`, `1h2m3s` or `1:02:03` style offsets.
+fn parse_time_offset(value: &str) -> Option<Duration> {
+    if value.contains(':') {
+        return parse_timestamp(value);
+    }
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let seconds = number.parse::<u64>().ok()?.checked_mul(unit)?;
+        total = total.checked_add(seconds)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total = total.checked_add(number.parse::<u64>().ok()?)?;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+pub fn youtube_playlist_url(playlist_id: &str) -> String {
+    format!("https://www.youtube.com/playlist?list={}", playlist_id)
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split(['&', '#'])
+        .find_map(|part| part.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn valid_playlist_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
 fn first_path_segment(path: &str) -> Option<&str> {
     path.split(['?', '&', '/'])
         .next()
         .filter(|segment| !segment.is_empty())
 }
 
-fn valid_youtube_id(id: &str) -> bool {
+pub fn valid_youtube_id(id: &str) -> bool {
     id.len() == 11
         && id
             .bytes()
@@ -76,6 +167,8 @@ const YTDLP_NOT_FOUND: &str =
     "yt-dlp executable not found; install yt-dlp and ensure it is in PATH";
 const FFMPEG_NOT_FOUND: &str =
     "ffmpeg executable not found; install ffmpeg and ensure it is in PATH";
+const FFPROBE_NOT_FOUND: &str =
+    "ffprobe executable not found; install ffmpeg (which ships ffprobe) and ensure it is in PATH";
 
 fn map_ytdlp_start_error(error: io::Error) -> io::Error {
     if error.kind() == io::ErrorKind::NotFound {
@@ -93,6 +186,14 @@ fn map_ffmpeg_start_error(error: io::Error) -> io::Error {
     }
 }
 
+fn map_ffprobe_start_error(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::NotFound {
+        io::Error::new(io::ErrorKind::NotFound, FFPROBE_NOT_FOUND)
+    } else {
+        error
+    }
+}
+
 fn ytdlp_failure(action: &str, output: &std::process::Output) -> io::Error {
     let stderr = String::from_utf8_lossy(&output.stderr);
     let message = stderr.trim();
@@ -104,73 +205,683 @@ fn ytdlp_failure(action: &str, output: &std::process::Output) -> io::Error {
     }
 }
 
-fn parse_duration_part(part: &str) -> io::Result<u64> {
-    part.parse::<u64>().map_err(|error| {
-        io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("couldn't parse yt-dlp duration segment '{part}': {error}"),
-        )
-    })
+#[derive(Debug, Deserialize)]
+struct FlatPlaylist {
+    #[serde(default, deserialize_with = "null_as_default")]
+    entries: Vec<VideoMetadata>,
 }
 
-pub async fn get_video_title(video_url: &str) -> Result<String, io::Error> {
+pub async fn get_playlist_entries(
+    playlist_url: &str,
+    limit: usize,
+) -> io::Result<Vec<VideoMetadata>> {
     let output = TokioCommand::new("yt-dlp")
-        .arg("--get-title")
-        .arg(video_url)
+        .args(["--flat-playlist", "-J", "--playlist-end"])
+        .arg(limit.to_string())
+        .arg(playlist_url)
         .output()
         .await
         .map_err(map_ytdlp_start_error)?;
 
     if !output.status.success() {
-        return Err(ytdlp_failure("get video title", &output));
+        return Err(ytdlp_failure("list playlist", &output));
+    }
+
+    parse_flat_playlist(&output.stdout, limit)
+}
+
+fn parse_flat_playlist(json: &[u8], limit: usize) -> io::Result<Vec<VideoMetadata>> {
+    let playlist: FlatPlaylist = serde_json::from_slice(json)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    // Private and deleted videos show up without a duration and can't be played.
+    // Flat entries only carry a bare `url`, so point `webpage_url` at the video.
+    Ok(playlist
+        .entries
+        .into_iter()
+        .filter(|entry| valid_youtube_id(&entry.id) && entry.duration.is_some())
+        .map(|entry| VideoMetadata {
+            webpage_url: format!("https://www.youtube.com/watch?v={}", entry.id),
+            ..entry
+        })
+        .take(limit)
+        .collect())
+}
+
+/// Formats a total running time as `2h14m`, or `3m05s` when under an hour.
+pub fn format_total_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekTarget {
+    To(Duration),
+    Forward(Duration),
+    Back(Duration),
+}
+
+impl SeekTarget {
+    pub fn resolve(self, position: Duration, length: Option<Duration>) -> Duration {
+        let target = match self {
+            SeekTarget::To(target) => target,
+            SeekTarget::Forward(by) => position.saturating_add(by),
+            SeekTarget::Back(by) => position.saturating_sub(by),
+        };
+
+        length.map_or(target, |length| target.min(length))
+    }
+}
+
+pub fn parse_seek_target(input: &str) -> Option<SeekTarget> {
+    let input = input.trim();
+    if let Some(rest) = input.strip_prefix('+') {
+        parse_timestamp(rest).map(SeekTarget::Forward)
+    } else if let Some(rest) = input.strip_prefix('-') {
+        parse_timestamp(rest).map(SeekTarget::Back)
+    } else {
+        parse_timestamp(input).map(SeekTarget::To)
+    }
+}
+
+pub fn parse_timestamp(input: &str) -> Option<Duration> {
+    let parts: Vec<&str> = input.trim().split(':').collect();
+    if parts.len() > 3 || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+
+    parts.iter().try_fold(Duration::ZERO, |total, part| {
+        let value = part.parse::<u64>().ok()?;
+        total
+            .checked_mul(60)?
+            .checked_add(Duration::from_secs(value))
+    })
+}
+
+/// Field names follow yt-dlp's `-J` output.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VideoMetadata {
+    #[serde(deserialize_with = "null_as_default")]
+    pub id: String,
+    #[serde(deserialize_with = "null_as_default")]
+    pub title: String,
+    pub uploader: Option<String>,
+    #[serde(deserialize_with = "optional_seconds")]
+    pub duration: Option<Duration>,
+    pub thumbnail: Option<String>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub is_live: bool,
+    #[serde(deserialize_with = "null_as_default")]
+    pub chapters: Vec<Chapter>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub webpage_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Chapter {
+    #[serde(deserialize_with = "seconds")]
+    pub start_time: Duration,
+    #[serde(deserialize_with = "seconds")]
+    pub end_time: Duration,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub title: String,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn optional_seconds<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<f64>::deserialize(deserializer)?
+        .filter(|secs| secs.is_finite() && *secs > 0.0)
+        .map(Duration::from_secs_f64))
+}
+
+fn seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(optional_seconds(deserializer)?.unwrap_or_default())
+}
+
+#[async_trait]
+pub trait SourceResolver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn handles(&self, url: &str) -> bool;
+
+    async fn metadata(&self, url: &str) -> io::Result<VideoMetadata>;
+
+    fn create_input(&self, url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input>;
+}
+
+/// Checked in order, so the catch-all yt-dlp resolver has to stay last.
+static SOURCE_RESOLVERS: [&dyn SourceResolver; 4] = [
+    &YouTubeResolver,
+    &DirectAudioResolver,
+    &LocalFileResolver,
+    &YtDlpResolver,
+];
+
+pub fn resolve_source(url: &str) -> Option<&'static dyn SourceResolver> {
+    SOURCE_RESOLVERS
+        .iter()
+        .copied()
+        .find(|resolver| resolver.handles(url))
+}
+
+pub async fn get_source_metadata(url: &str) -> io::Result<VideoMetadata> {
+    let resolver = resolve_source(url)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported URL"))?;
+    resolver.metadata(url).await
+}
+
+pub fn create_source_input(url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+    let resolver = resolve_source(url)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported URL"))?;
+    resolver.create_input(url, start, filter)
+}
+
+pub struct YouTubeResolver;
+
+#[async_trait]
+impl SourceResolver for YouTubeResolver {
+    fn name(&self) -> &'static str {
+        "youtube"
     }
 
-    String::from_utf8(output.stdout)
+    fn handles(&self, url: &str) -> bool {
+        is_valid_youtube_url(url)
+    }
+
+    async fn metadata(&self, url: &str) -> io::Result<VideoMetadata> {
+        ytdlp_metadata(url).await
+    }
+
+    fn create_input(&self, url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+        ytdlp_audio_input(url, start, filter)
+    }
+}
+
+pub struct YtDlpResolver;
+
+#[async_trait]
+impl SourceResolver for YtDlpResolver {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    fn handles(&self, url: &str) -> bool {
+        url_host(url).is_some()
+    }
+
+    async fn metadata(&self, url: &str) -> io::Result<VideoMetadata> {
+        ytdlp_metadata(url).await
+    }
+
+    fn create_input(&self, url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+        ytdlp_audio_input(url, start, filter)
+    }
+}
+
+const AUDIO_FILE_EXTENSIONS: [&str; 4] = ["mp3", "ogg", "wav", "flac"];
+
+pub fn audio_file_extension(file_name: &str) -> Option<&str> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    AUDIO_FILE_EXTENSIONS
+        .iter()
+        .any(|known| extension.eq_ignore_ascii_case(known))
+        .then_some(extension)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioProbe {
+    pub duration: Duration,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+pub fn probe_audio_bytes(bytes: Vec<u8>, extension: Option<&str>) -> io::Result<AudioProbe> {
+    probe_audio(Box::new(io::Cursor::new(bytes)), extension, 1.0)
+}
+
+/// Probes the first bytes of a `total_len` byte file. When the headers don't
+/// give the length, it's estimated from how much of the file the packets cover.
+pub fn probe_audio_prefix(
+    prefix: Vec<u8>,
+    total_len: u64,
+    extension: Option<&str>,
+) -> io::Result<AudioProbe> {
+    if prefix.len() as u64 >= total_len {
+        return probe_audio_bytes(prefix, extension);
+    }
+
+    let share = prefix.len() as f64 / total_len as f64;
+    // Unseekable, so readers can't mistake the end of the prefix for the end of the file.
+    let source = ReadOnlySource::new(io::Cursor::new(prefix));
+    probe_audio(Box::new(source), extension, share)
+}
+
+pub async fn download_prefix(url: &str, limit: usize) -> io::Result<Vec<u8>> {
+    let mut response = reqwest::Client::new()
+        .get(url)
+        .header(reqwest::header::RANGE, format!("bytes=0-{}", limit - 1))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(io::Error::other)?;
+
+    // Servers may ignore the range, so stop reading once there's enough.
+    let mut bytes = Vec::new();
+    while bytes.len() < limit {
+        match response.chunk().await.map_err(io::Error::other)? {
+            Some(chunk) => bytes.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    bytes.truncate(limit);
+    Ok(bytes)
+}
+
+pub fn probe_audio_file(path: &Path) -> io::Result<AudioProbe> {
+    let file = File::open(path)?;
+    probe_audio(
+        Box::new(file),
+        path.extension().and_then(|extension| extension.to_str()),
+        1.0,
+    )
+}
+
+/// Reads an audio file's tags and length from its headers, counting packets when
+/// the container doesn't say (e.g. MP3s without a Xing header).
+fn probe_audio(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+    share: f64,
+) -> io::Result<AudioProbe> {
+    let mut probed = probe_format(source, extension)?;
+
+    let mut probe = AudioProbe::default();
+    if let Some(revision) = probed
+        .metadata
+        .get()
+        .as_ref()
+        .and_then(|meta| meta.current())
+    {
+        read_tags(revision, &mut probe);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        read_tags(revision, &mut probe);
+    }
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no audio track found"))?;
+    let track_id = track.id;
+    let time_base = track
+        .codec_params
+        .time_base
+        .or_else(|| {
+            track
+                .codec_params
+                .sample_rate
+                .map(|rate| TimeBase::new(1, rate))
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown sample rate"))?;
+
+    let frames = match track.codec_params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut frames = 0;
+            while let Ok(packet) = probed.format.next_packet() {
+                if packet.track_id() == track_id {
+                    frames += packet.dur;
+                }
+            }
+            (frames as f64 / share) as u64
+        }
+    };
+
+    let time = time_base.calc_time(frames);
+    probe.duration = Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac);
+    Ok(probe)
+}
+
+fn probe_format(source: Box<dyn MediaSource>, extension: Option<&str>) -> io::Result<ProbeResult> {
+    let source = MediaSourceStream::new(source, Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
         .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
 }
 
-pub async fn get_video_duration(video_url: &str) -> io::Result<Duration> {
+fn read_tags(revision: &MetadataRevision, probe: &mut AudioProbe) {
+    for tag in revision.tags() {
+        let slot = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => &mut probe.title,
+            Some(StandardTagKey::Artist) => &mut probe.artist,
+            _ => continue,
+        };
+        let value = tag.value.to_string();
+        if slot.is_none() && !value.trim().is_empty() {
+            *slot = Some(value.trim().to_string());
+        }
+    }
+}
+
+pub fn file_input(path: &Path, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+    let Some(filter) = filter else {
+        return decode_file_input(path, start);
+    };
+
+    let ffmpeg = ffmpeg_pcm_command(start)
+        .arg("-i")
+        .arg(path)
+        .args(["-af", filter])
+        .args(FFMPEG_PCM_OUTPUT)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(map_ffmpeg_start_error)?;
+
+    Ok(pcm_input(vec![ffmpeg]))
+}
+
+/// Decodes a file in-process with symphonia, starting at `start`, and feeds the
+/// samples through the same `RawAdapter` path the ffmpeg pipelines use.
+pub fn decode_file_input(path: &Path, start: Duration) -> io::Result<Input> {
+    let file = File::open(path)?;
+    let mut probed = probe_format(
+        Box::new(file),
+        path.extension().and_then(|extension| extension.to_str()),
+    )?;
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no audio track found"))?;
+    let track_id = track.id;
+    let decoder = get_codec_registry()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let mut skip_frames = 0;
+    if !start.is_zero() {
+        let seeked = probed
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(start.as_secs_f64()),
+                    track_id: Some(track_id),
+                },
+            )
+            .map_err(io::Error::other)?;
+        skip_frames = seeked.required_ts.saturating_sub(seeked.actual_ts);
+    }
+
+    let mut decoded = DecodedAudio {
+        format: probed.format,
+        decoder,
+        track_id,
+        skip_frames,
+        pending: Vec::new(),
+        read_pos: 0,
+    };
+    // The first packet tells us the real sample rate and channel layout.
+    let spec = decoded
+        .decode_next()?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no audio after seek"))?;
+
+    Ok(RawAdapter::new(
+        ReadOnlySource::new(decoded),
+        spec.rate,
+        spec.channels.count() as u32,
+    )
+    .into())
+}
+
+struct DecodedAudio {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    skip_frames: u64,
+    pending: Vec<u8>,
+    read_pos: usize,
+}
+
+impl DecodedAudio {
+    fn decode_next(&mut self) -> io::Result<Option<SignalSpec>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(error))
+                    if error.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None);
+                }
+                Err(error) => return Err(io::Error::other(error)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let audio = match self.decoder.decode(&packet) {
+                Ok(audio) => audio,
+                Err(SymphoniaError::DecodeError(why)) => {
+                    println!("Skipping undecodable packet: {}", why);
+                    continue;
+                }
+                Err(error) => return Err(io::Error::other(error)),
+            };
+            let spec = *audio.spec();
+            let channels = spec.channels.count();
+            let mut samples = SampleBuffer::<f32>::new(audio.capacity() as u64, spec);
+            samples.copy_interleaved_ref(audio);
+
+            let skip = self.skip_frames.min((samples.len() / channels) as u64);
+            self.skip_frames -= skip;
+            self.pending.clear();
+            self.read_pos = 0;
+            for sample in &samples.samples()[skip as usize * channels..] {
+                self.pending.extend_from_slice(&sample.to_le_bytes());
+            }
+            if !self.pending.is_empty() {
+                return Ok(Some(spec));
+            }
+        }
+    }
+}
+
+impl io::Read for DecodedAudio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read_pos >= self.pending.len() && self.decode_next()?.is_none() {
+            return Ok(0);
+        }
+
+        let count = (self.pending.len() - self.read_pos).min(buf.len());
+        buf[..count].copy_from_slice(&self.pending[self.read_pos..self.read_pos + count]);
+        self.read_pos += count;
+        Ok(count)
+    }
+}
+
+pub struct DirectAudioResolver;
+
+#[async_trait]
+impl SourceResolver for DirectAudioResolver {
+    fn name(&self) -> &'static str {
+        "direct"
+    }
+
+    fn handles(&self, url: &str) -> bool {
+        url_host(url).is_some() && url_file_name(url).and_then(audio_file_extension).is_some()
+    }
+
+    async fn metadata(&self, url: &str) -> io::Result<VideoMetadata> {
+        let output = TokioCommand::new("ffprobe")
+            .args([
+                "-v",
+                "error",
+                "-show_entries",
+                "format=duration",
+                "-of",
+                "default=noprint_wrappers=1:nokey=1",
+                url,
+            ])
+            .output()
+            .await
+            .map_err(map_ffprobe_start_error)?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!(
+                "ffprobe failed to read audio file: {}",
+                stderr.trim()
+            )));
+        }
+
+        // Icecast and other endless streams report no duration at all.
+        let duration = String::from_utf8_lossy(&output.stdout);
+        let duration = match duration.trim() {
+            "" | "N/A" => None,
+            duration => Some(parse_seconds(duration)?),
+        };
+        Ok(VideoMetadata {
+            title: url_file_name(url).unwrap_or(url).to_string(),
+            duration,
+            is_live: duration.is_none(),
+            webpage_url: url.to_string(),
+            ..VideoMetadata::default()
+        })
+    }
+
+    fn create_input(&self, url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+        let ffmpeg = ffmpeg_pcm_command(start)
+            .args(FFMPEG_OPTIONS)
+            .args(["-i", url])
+            .args(filter_args(filter))
+            .args(FFMPEG_PCM_OUTPUT)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(map_ffmpeg_start_error)?;
+
+        Ok(pcm_input(vec![ffmpeg]))
+    }
+}
+
+fn strip_http_scheme(url: &str) -> Option<&str> {
+    url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+}
+
+fn url_host(url: &str) -> Option<&str> {
+    strip_http_scheme(url)?
+        .split(['/', '?', '#'])
+        .next()
+        .filter(|host| host.contains('.'))
+}
+
+fn url_file_name(url: &str) -> Option<&str> {
+    let rest = strip_http_scheme(url)?.split(['?', '#']).next()?;
+    let (_, path) = rest.split_once('/')?;
+    path.rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+fn parse_seconds(value: &str) -> io::Result<Duration> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("couldn't parse duration '{value}'"),
+            )
+        })
+}
+
+async fn ytdlp_metadata(url: &str) -> io::Result<VideoMetadata> {
     let output = TokioCommand::new("yt-dlp")
-        .arg("--get-duration")
-        .arg(video_url)
+        .args(["-J", "--no-playlist", url])
         .output()
         .await
         .map_err(map_ytdlp_start_error)?;
 
     if !output.status.success() {
-        return Err(ytdlp_failure("get video duration", &output));
+        return Err(ytdlp_failure("read metadata", &output));
     }
 
-    let duration_str = String::from_utf8(output.stdout)
-        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
-    let duration_parts: Vec<&str> = duration_str.trim().split(':').collect();
-
-    match duration_parts.as_slice() {
-        [hrs, mins, secs] => Ok(Duration::from_secs(
-            parse_duration_part(hrs)? * 3600
-                + parse_duration_part(mins)? * 60
-                + parse_duration_part(secs)?,
-        )),
-        [mins, secs] => Ok(Duration::from_secs(
-            parse_duration_part(mins)? * 60 + parse_duration_part(secs)?,
-        )),
-        [secs] => Ok(Duration::from_secs(parse_duration_part(secs)?)),
-        _ => Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "couldn't parse yt-dlp duration",
-        )),
-    }
-}
-
-pub fn create_youtube_audio_input(video_url: &str) -> io::Result<Input> {
+    let mut metadata = parse_video_metadata(&output.stdout)?;
+    if metadata.webpage_url.is_empty() {
+        metadata.webpage_url = url.to_string();
+    }
+    Ok(metadata)
+}
+
+fn parse_video_metadata(json: &[u8]) -> io::Result<VideoMetadata> {
+    serde_json::from_slice(json).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+const FFMPEG_PCM_OUTPUT: [&str; 7] = ["-f", "f32le", "-ac", "2", "-ar", "48000", "pipe:1"];
+
+/// An ffmpeg invocation with the shared flags; callers add the input and output.
+fn ffmpeg_pcm_command(start: Duration) -> Command {
+    let mut ffmpeg_command = Command::new("ffmpeg");
+    ffmpeg_command.args(["-hide_banner", "-loglevel", "warning"]);
+    if !start.is_zero() {
+        ffmpeg_command.args(["-ss", &format!("{:.3}", start.as_secs_f64())]);
+    }
+    ffmpeg_command
+}
+
+fn filter_args(filter: Option<&str>) -> Vec<&str> {
+    filter.map(|filter| vec!["-af", filter]).unwrap_or_default()
+}
+
+fn pcm_input(children: Vec<std::process::Child>) -> Input {
+    let process_chain = ChildContainer::new(children);
+    let process_source = ReadOnlySource::new(process_chain);
+    RawAdapter::new(process_source, 48_000, 2).into()
+}
+
+fn spawn_ytdlp_audio(url: &str) -> io::Result<(std::process::Child, std::process::ChildStdout)> {
     let mut ytdlp = Command::new("yt-dlp")
         .args([
             "-f",
             "ba[abr>0][vcodec=none]/best",
             "--no-playlist",
+            "--playlist-items",
+            "1",
             "-o",
             "-",
-            video_url,
+            url,
         ])
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
@@ -182,38 +893,159 @@ pub fn create_youtube_audio_input(video_url: &str) -> io::Result<Input> {
         .stdout
         .take()
         .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "yt-dlp stdout unavailable"))?;
+    Ok((ytdlp, ytdlp_stdout))
+}
 
-    let ffmpeg = match Command::new("ffmpeg")
+fn ytdlp_audio_input(url: &str, start: Duration, filter: Option<&str>) -> io::Result<Input> {
+    let (mut ytdlp, ytdlp_stdout) = spawn_ytdlp_audio(url)?;
+
+    // The piped stream isn't seekable, so seeking restarts the chain and lets
+    // ffmpeg skip ahead to `start` instead. yt-dlp retries dropped downloads
+    // itself, so ffmpeg's HTTP reconnect flags don't apply here.
+    let ffmpeg = match ffmpeg_pcm_command(start)
+        .args(["-i", "pipe:0"])
+        .args(filter_args(filter))
+        .args(FFMPEG_PCM_OUTPUT)
+        .stdin(Stdio::from(ytdlp_stdout))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(error) => {
+            let _ = ytdlp.kill();
+            let _ = ytdlp.wait();
+            return Err(map_ffmpeg_start_error(error));
+        }
+    };
+
+    Ok(pcm_input(vec![ytdlp, ffmpeg]))
+}
+
+/// Plays `url` like `ytdlp_audio_input` while encoding the same download to
+/// 48kHz stereo FLAC at `path`, so a song is only fetched once. `on_stored` gets
+/// whether the copy is complete once yt-dlp is done, even if playback stopped early.
+pub fn ytdlp_audio_input_storing(
+    url: &str,
+    start: Duration,
+    filter: Option<&str>,
+    path: &Path,
+    on_stored: impl FnOnce(io::Result<()>) + Send + 'static,
+) -> io::Result<Input> {
+    let (mut ytdlp, ytdlp_stdout) = spawn_ytdlp_audio(url)?;
+
+    let mut ffmpeg = match ffmpeg_pcm_command(start)
+        .args(["-i", "pipe:0"])
+        .args(filter_args(filter))
+        .args(FFMPEG_PCM_OUTPUT)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(error) => {
+            let _ = ytdlp.kill();
+            let _ = ytdlp.wait();
+            return Err(map_ffmpeg_start_error(error));
+        }
+    };
+    let mut encoder = match Command::new("ffmpeg")
         .args([
             "-hide_banner",
             "-loglevel",
-            "warning",
+            "error",
+            "-y",
             "-i",
             "pipe:0",
-            "-f",
-            "f32le",
+            "-vn",
+        ])
+        .args([
             "-ac",
             "2",
             "-ar",
             "48000",
-            "pipe:1",
+            "-c:a",
+            "flac",
+            "-sample_fmt",
+            "s16",
         ])
-        .stdin(Stdio::from(ytdlp_stdout))
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
+        .args(["-f", "flac"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
         .spawn()
     {
         Ok(child) => child,
         Err(error) => {
-            let _ = ytdlp.kill();
-            let _ = ytdlp.wait();
+            for child in [&mut ytdlp, &mut ffmpeg] {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
             return Err(map_ffmpeg_start_error(error));
         }
     };
 
-    let process_chain = ChildContainer::new(vec![ytdlp, ffmpeg]);
-    let process_source = ReadOnlySource::new(process_chain);
-    Ok(RawAdapter::new(process_source, 48_000, 2).into())
+    let playback = ffmpeg.stdin.take();
+    let storage = encoder.stdin.take();
+    std::thread::spawn(move || {
+        let copied = tee_download(ytdlp_stdout, playback, storage);
+        if copied.is_err() {
+            let _ = ytdlp.kill();
+        }
+        let ytdlp_status = ytdlp.wait();
+        let encoded = encoder.wait_with_output();
+
+        on_stored(copied.and_then(|()| {
+            if !ytdlp_status?.success() {
+                return Err(io::Error::other("yt-dlp failed to download audio"));
+            }
+            let output = encoded?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(io::Error::other(format!(
+                    "ffmpeg failed to encode audio: {}",
+                    stderr.trim()
+                )));
+            }
+            Ok(())
+        }));
+    });
+
+    Ok(pcm_input(vec![ffmpeg]))
+}
+
+/// Copies the download to playback and storage. Playback going away (a skip or
+/// a stop) only ends that copy; the stored one carries on to the end.
+fn tee_download(
+    mut download: impl Read,
+    mut playback: Option<impl Write>,
+    mut storage: Option<impl Write>,
+) -> io::Result<()> {
+    let mut stored = Ok(());
+    let mut buffer = [0; 64 * 1024];
+    while playback.is_some() || storage.is_some() {
+        let read = match download.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        if let Some(stdin) = playback.as_mut() {
+            if stdin.write_all(&buffer[..read]).is_err() {
+                playback = None;
+            }
+        }
+        if let Some(stdin) = storage.as_mut() {
+            if let Err(why) = stdin.write_all(&buffer[..read]) {
+                stored = Err(why);
+                storage = None;
+            }
+        }
+    }
+
+    stored
 }
 
 pub async fn send_large_message(
@@ -311,13 +1143,22 @@ pub async fn get_searched_url(
 
 #[cfg(test)]
 mod tests {
-    use super::{extract_youtube_url, is_valid_youtube_url, split_large_message};
+    use super::{
+        audio_file_extension, parse_video_metadata, probe_audio_bytes, probe_audio_prefix,
+        resolve_source, tee_download, url_file_name,
+    };
+    use super::{
+        extract_source_url, format_total_duration, is_valid_youtube_url, parse_flat_playlist,
+        parse_seek_target, parse_start_offset, parse_timestamp, split_large_message,
+        youtube_playlist_page_id, youtube_video_playlist_id, SeekTarget,
+    };
+    use std::time::Duration;
 
     #[test]
-    fn extracts_only_the_youtube_url_token() {
+    fn extracts_only_the_url_token() {
         let input = "play <https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=10s>, please";
         assert_eq!(
-            extract_youtube_url(input).unwrap(),
+            extract_source_url(input).unwrap(),
             "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=10s"
         );
     }
@@ -344,7 +1185,7 @@ mod tests {
         assert!(!is_valid_youtube_url(
             "https://www.youtube.com/watch?v=short"
         ));
-        assert!(extract_youtube_url("https://example.com nope").is_err());
+        assert!(extract_source_url("example.com nope").is_err());
     }
 
     #[test]
@@ -354,4 +1195,253 @@ mod tests {
         assert_eq!(parts.concat(), message);
         assert!(parts.iter().all(|part| part.len() <= 1950));
     }
+
+    #[test]
+    fn parses_timestamps_and_seek_targets() {
+        assert_eq!(parse_timestamp("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("1:30"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("1:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_timestamp("1::3"), None);
+        assert_eq!(parse_timestamp("soon"), None);
+
+        assert_eq!(
+            parse_seek_target("+30"),
+            Some(SeekTarget::Forward(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            parse_seek_target("-15"),
+            Some(SeekTarget::Back(Duration::from_secs(15)))
+        );
+        assert_eq!(
+            parse_seek_target("1:30"),
+            Some(SeekTarget::To(Duration::from_secs(90)))
+        );
+    }
+
+    #[test]
+    fn seek_targets_stay_within_the_track() {
+        let position = Duration::from_secs(10);
+        let length = Duration::from_secs(60);
+        assert_eq!(
+            SeekTarget::Back(Duration::from_secs(15)).resolve(position, Some(length)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            SeekTarget::Forward(Duration::from_secs(90)).resolve(position, Some(length)),
+            length
+        );
+        assert_eq!(
+            SeekTarget::Forward(Duration::from_secs(90)).resolve(position, None),
+            Duration::from_secs(100)
+        );
+    }
+
+    #[test]
+    fn recognises_playlist_links() {
+        let playlist = "https://www.youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf";
+        assert!(is_valid_youtube_url(playlist));
+        assert_eq!(
+            youtube_playlist_page_id(playlist),
+            Some("PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf")
+        );
+        assert_eq!(youtube_video_playlist_id(playlist), None);
+
+        let video = "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123&index=2";
+        assert_eq!(youtube_playlist_page_id(video), None);
+        assert_eq!(youtube_video_playlist_id(video), Some("PL123"));
+        assert_eq!(
+            youtube_video_playlist_id("https://youtu.be/dQw4w9WgXcQ"),
+            None
+        );
+    }
+
+    #[test]
+    fn flat_playlists_skip_unplayable_entries_and_respect_the_cap() {
+        let json = br#"{"entries": [
+            {"id": "dQw4w9WgXcQ", "title": "First", "duration": 212.0, "url": "dQw4w9WgXcQ"},
+            {"id": "aaaaaaaaaaa", "duration": null},
+            {"id": "bbbbbbbbbbb", "duration": 61},
+            {"id": "ccccccccccc", "duration": 30}
+        ]}"#;
+        let entries = parse_flat_playlist(json, 2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].webpage_url,
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+        assert_eq!(entries[0].title, "First");
+        assert_eq!(entries[1].duration, Some(Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn formats_total_durations() {
+        assert_eq!(format_total_duration(Duration::from_secs(8040)), "2h14m");
+        assert_eq!(format_total_duration(Duration::from_secs(185)), "3m05s");
+    }
+
+    #[test]
+    fn picks_a_resolver_for_each_kind_of_link() {
+        let name = |url| resolve_source(url).map(|resolver| resolver.name());
+        assert_eq!(name("https://youtu.be/dQw4w9WgXcQ"), Some("youtube"));
+        assert_eq!(
+            name("https://cdn.example.com/music/Song%201.FLAC?token=abc"),
+            Some("direct")
+        );
+        assert_eq!(name("https://example.com/radio.ogg#live"), Some("direct"));
+        assert_eq!(
+            name("https://soundcloud.com/artist/some-track"),
+            Some("yt-dlp")
+        );
+        assert_eq!(name("https://example.com/track.mp3.html"), Some("yt-dlp"));
+        assert_eq!(name("not a url"), None);
+        assert_eq!(name("https://localhost"), None);
+    }
+
+    #[test]
+    fn extracts_any_playable_url() {
+        assert_eq!(
+            extract_source_url("listen <https://artist.bandcamp.com/track/song>!").unwrap(),
+            "https://artist.bandcamp.com/track/song"
+        );
+        assert!(extract_source_url("play something nice").is_err());
+        assert_eq!(
+            url_file_name("https://example.com/a/b/song.mp3?x=1"),
+            Some("song.mp3")
+        );
+        assert_eq!(url_file_name("https://example.com"), None);
+    }
+
+    #[test]
+    fn parses_ytdlp_json_metadata() {
+        let json = br#"{
+            "id": "dQw4w9WgXcQ",
+            "title": "Some Song",
+            "uploader": "Someone",
+            "duration": 212.5,
+            "thumbnail": "https://i.ytimg.com/vi/dQw4w9WgXcQ/maxresdefault.jpg",
+            "is_live": false,
+            "chapters": [{"start_time": 0.0, "end_time": 60.0, "title": "Intro"}],
+            "webpage_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "formats": []
+        }"#;
+        let metadata = parse_video_metadata(json).unwrap();
+        assert_eq!(metadata.title, "Some Song");
+        assert_eq!(metadata.uploader.as_deref(), Some("Someone"));
+        assert_eq!(metadata.duration, Some(Duration::from_millis(212_500)));
+        assert_eq!(metadata.chapters[0].end_time, Duration::from_secs(60));
+
+        let long = parse_video_metadata(br#"{"title": "Marathon", "duration": 100000}"#).unwrap();
+        assert_eq!(long.duration, Some(Duration::from_secs(100_000)));
+
+        let live = parse_video_metadata(
+            br#"{"title": "Radio", "duration": null, "is_live": true, "chapters": null}"#,
+        )
+        .unwrap();
+        assert!(live.is_live);
+        assert_eq!(live.duration, None);
+        assert!(live.chapters.is_empty());
+        assert!(parse_video_metadata(b"").is_err());
+    }
+
+    #[test]
+    fn recognises_audio_file_names() {
+        assert_eq!(audio_file_extension("Song.FLAC"), Some("FLAC"));
+        assert_eq!(audio_file_extension("voice.note.ogg"), Some("ogg"));
+        assert_eq!(audio_file_extension("notes.txt"), None);
+        assert_eq!(audio_file_extension("wav"), None);
+    }
+
+    #[test]
+    fn probes_wav_durations_from_headers() {
+        let sample_rate: u32 = 8_000;
+        let data = vec![0_u8; sample_rate as usize * 2 * 3];
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16_u32.to_le_bytes());
+        wav.extend_from_slice(&1_u16.to_le_bytes());
+        wav.extend_from_slice(&1_u16.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2_u16.to_le_bytes());
+        wav.extend_from_slice(&16_u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+
+        assert_eq!(
+            probe_audio_bytes(wav.clone(), Some("wav"))
+                .unwrap()
+                .duration,
+            Duration::from_secs(3)
+        );
+        assert!(probe_audio_bytes(b"not audio".to_vec(), None).is_err());
+
+        let total_len = wav.len() as u64;
+        let prefix = wav[..1024].to_vec();
+        assert_eq!(
+            probe_audio_prefix(prefix, total_len, Some("wav"))
+                .unwrap()
+                .duration,
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn keeps_storing_a_download_after_playback_stops() {
+        struct Skipped;
+        impl std::io::Write for Skipped {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let download = vec![7_u8; 200 * 1024];
+        let mut stored = Vec::new();
+        tee_download(download.as_slice(), Some(Skipped), Some(&mut stored)).unwrap();
+        assert_eq!(stored, download);
+    }
+
+    #[test]
+    fn rejects_timestamps_that_overflow() {
+        assert_eq!(parse_timestamp("307445734561825861:0"), None);
+        assert_eq!(parse_timestamp("18446744073709551615:59:59"), None);
+        assert_eq!(parse_seek_target("+307445734561825861:0"), None);
+    }
+
+    #[test]
+    fn parses_start_offsets_from_links() {
+        let offset = |url| parse_start_offset(url).map(|offset| offset.as_secs());
+        assert_eq!(
+            offset("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42"),
+            Some(42)
+        );
+        assert_eq!(offset("https://youtu.be/dQw4w9WgXcQ?t=1h2m3s"), Some(3723));
+        assert_eq!(offset("https://youtu.be/dQw4w9WgXcQ#t=90s"), Some(90));
+        assert_eq!(
+            offset("https://www.youtube.com/watch?t=2m&v=dQw4w9WgXcQ"),
+            Some(120)
+        );
+        assert_eq!(offset("https://example.com/a.mp3?t=1:30"), Some(90));
+        assert_eq!(offset("https://youtu.be/dQw4w9WgXcQ?t=soon"), None);
+        assert_eq!(offset("https://youtu.be/dQw4w9WgXcQ?list=t=5"), None);
+        assert_eq!(offset("https://youtu.be/dQw4w9WgXcQ"), None);
+    }
+
+    #[test]
+    fn rejects_start_offsets_that_overflow() {
+        let offset = |url| parse_start_offset(url).map(|offset| offset.as_secs());
+        assert_eq!(
+            offset("https://youtu.be/dQw4w9WgXcQ?t=6000000000000000h"),
+            None
+        );
+        assert_eq!(
+            offset("https://youtu.be/dQw4w9WgXcQ?t=18446744073709551615s1s"),
+            None
+        );
+    }
 }